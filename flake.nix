@@ -57,6 +57,7 @@
          , libXrandr
          , libXi
          , libX11
+         , alsa-lib
            # GTK, for the file picker
          , wrapGAppsHook
          , glib
@@ -94,6 +95,7 @@
               libXcursor
               libxkbcommon
               libXi
+              alsa-lib
               libGL
               fontconfig
               wayland
@@ -0,0 +1,151 @@
+use super::units::Timer;
+use crate::{region::Region, savestate::snapshot_fields, util};
+
+/// https://www.nesdev.org/wiki/APU_DMC
+#[derive(Default)]
+pub struct DeltaModulation {
+    pub interrupt_flag: bool,
+    interrupt_enabled: bool,
+    looping: bool,
+    timer: Timer,
+
+    sample_address: u16,
+    sample_length: u16,
+    current_address: u16,
+    bytes_remaining: u16,
+    sample_buffer: Option<u8>,
+
+    shift_register: u8,
+    bits_remaining: u8,
+    silence: bool,
+    output_level: u8,
+
+    pub region: Region,
+}
+
+snapshot_fields!(DeltaModulation {
+    interrupt_flag,
+    interrupt_enabled,
+    looping,
+    timer,
+    sample_address,
+    sample_length,
+    current_address,
+    bytes_remaining,
+    sample_buffer,
+    shift_register,
+    bits_remaining,
+    silence,
+    output_level,
+});
+
+impl DeltaModulation {
+    const RATE_TABLE: [u16; 16] = [
+        428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+    ];
+    const RATE_TABLE_PAL: [u16; 16] = [
+        398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+    ];
+
+    pub fn write_register(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.interrupt_enabled = util::nth_bit(data, 7);
+                self.looping = util::nth_bit(data, 6);
+                let table = if self.region.pal_apu() {
+                    &Self::RATE_TABLE_PAL
+                } else {
+                    &Self::RATE_TABLE
+                };
+                self.timer.period = table[(data & 0b0000_1111) as usize] - 1;
+                if !self.interrupt_enabled {
+                    self.interrupt_flag = false;
+                }
+            }
+            1 => self.output_level = data & 0b0111_1111,
+            2 => self.sample_address = 0xC000 | ((data as u16) << 6),
+            3 => self.sample_length = ((data as u16) << 4) | 1,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.interrupt_flag = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    pub const fn active(&self) -> bool {
+        self.bytes_remaining > 0
+    }
+
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    /// The address of the next sample byte, if the memory reader needs to fetch one.
+    /// The bus is responsible for reading it and handing it over with `load_sample`.
+    pub const fn sample_request(&self) -> Option<u16> {
+        if self.sample_buffer.is_none() && self.bytes_remaining > 0 {
+            Some(self.current_address)
+        } else {
+            None
+        }
+    }
+
+    pub fn load_sample(&mut self, data: u8) {
+        self.sample_buffer = Some(data);
+        // The address wraps around to $8000 instead of $0000
+        self.current_address = self.current_address.checked_add(1).unwrap_or(0x8000);
+        self.bytes_remaining -= 1;
+
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.interrupt_enabled {
+                self.interrupt_flag = true;
+            }
+        }
+    }
+
+    /// Clocked every CPU cycle
+    pub fn clock_timer(&mut self) {
+        if !self.timer.clock() {
+            return;
+        }
+
+        if !self.silence {
+            if util::nth_bit(self.shift_register, 0) {
+                if self.output_level <= 125 {
+                    self.output_level += 2;
+                }
+            } else if self.output_level >= 2 {
+                self.output_level -= 2;
+            }
+        }
+        self.shift_register >>= 1;
+
+        if self.bits_remaining > 0 {
+            self.bits_remaining -= 1;
+        }
+
+        if self.bits_remaining == 0 {
+            // Start a new output cycle
+            self.bits_remaining = 8;
+            if let Some(sample) = self.sample_buffer.take() {
+                self.silence = false;
+                self.shift_register = sample;
+            } else {
+                self.silence = true;
+            }
+        }
+    }
+
+    pub const fn output(&self) -> u8 {
+        self.output_level
+    }
+}
@@ -0,0 +1,8 @@
+//! Sound chips found on some cartridges, their output is mixed in with the APU's.
+//! https://www.nesdev.org/wiki/Expansion_audio
+
+mod namco163;
+mod sunsoft5b;
+mod vrc6;
+
+pub use {namco163::Namco163Audio, sunsoft5b::Sunsoft5bAudio, vrc6::Vrc6Audio};
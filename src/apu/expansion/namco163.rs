@@ -0,0 +1,117 @@
+use crate::{savestate::snapshot_fields, util};
+
+/// The wavetable synthesizer of the Namco 163, with up to eight channels that play 4-bit samples
+/// stored in its internal RAM. Channels are updated one at a time, so enabling more of them
+/// lowers the rate at which each one runs.
+/// https://www.nesdev.org/wiki/Namco_163_audio
+pub struct Namco163Audio {
+    ram: [u8; 0x80],
+    address: u8,
+    auto_increment: bool,
+
+    divider: u8,
+    current_channel: u8,
+    outputs: [i16; 8],
+}
+
+snapshot_fields!(Namco163Audio {
+    ram,
+    address,
+    auto_increment,
+    divider,
+    current_channel,
+    outputs,
+});
+
+impl Default for Namco163Audio {
+    fn default() -> Self {
+        Self {
+            ram: [0; 0x80],
+            address: 0,
+            auto_increment: false,
+            divider: 0,
+            current_channel: 0,
+            outputs: [0; 8],
+        }
+    }
+}
+
+impl Namco163Audio {
+    /// Each channel is updated every 15 CPU cycles
+    const CHANNEL_CYCLES: u8 = 15;
+    const CHANNEL_REGISTERS: usize = 0x40;
+
+    const LEVEL: f32 = 0.0025;
+
+    /// The address port, at $F800-$FFFF
+    pub fn write_address(&mut self, data: u8) {
+        self.address = data & 0b0111_1111;
+        self.auto_increment = util::nth_bit(data, 7);
+    }
+
+    /// The data port, at $4800-$4FFF
+    pub fn read_data(&mut self) -> u8 {
+        let data = self.ram[self.address as usize];
+        self.increment_address();
+        data
+    }
+
+    /// The data port, at $4800-$4FFF
+    pub fn write_data(&mut self, data: u8) {
+        self.ram[self.address as usize] = data;
+        self.increment_address();
+    }
+
+    fn increment_address(&mut self) {
+        if self.auto_increment {
+            self.address = (self.address + 1) & 0b0111_1111;
+        }
+    }
+
+    fn active_channels(&self) -> u8 {
+        ((self.ram[0x7F] >> 4) & 0b0000_0111) + 1
+    }
+
+    /// Advance by a single CPU cycle
+    pub fn clock(&mut self) {
+        self.divider += 1;
+        if self.divider < Self::CHANNEL_CYCLES {
+            return;
+        }
+        self.divider = 0;
+
+        // The active channels are always the last ones
+        let channel = 7 - self.current_channel;
+        self.update_channel(channel as usize);
+        self.current_channel = (self.current_channel + 1) % self.active_channels();
+    }
+
+    fn update_channel(&mut self, channel: usize) {
+        let base = Self::CHANNEL_REGISTERS + (channel * 8);
+        let registers = &mut self.ram[base..base + 8];
+
+        let frequency = registers[0] as u32
+            | (registers[2] as u32) << 8
+            | ((registers[4] & 0b0000_0011) as u32) << 16;
+        let phase = registers[1] as u32 | (registers[3] as u32) << 8 | (registers[5] as u32) << 16;
+        let length = 256 - (registers[4] & 0b1111_1100) as u32;
+
+        let phase = (phase + frequency) % (length << 16);
+        registers[1] = phase as u8;
+        registers[3] = (phase >> 8) as u8;
+        registers[5] = (phase >> 16) as u8;
+
+        let index = (((phase >> 16) + registers[6] as u32) & 0xFF) as usize;
+        let volume = (registers[7] & 0b0000_1111) as i16;
+        let sample = (self.ram[index / 2] >> ((index % 2) * 4)) & 0b0000_1111;
+
+        self.outputs[channel] = (sample as i16 - 8) * volume;
+    }
+
+    pub fn output(&self) -> f32 {
+        let active = self.active_channels() as usize;
+        let sum: i16 = self.outputs[8 - active..].iter().sum();
+        // The hardware multiplexes between channels, which averages out to this
+        sum as f32 / active as f32 * Self::LEVEL
+    }
+}
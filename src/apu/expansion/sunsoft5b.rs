@@ -0,0 +1,216 @@
+use crate::{savestate::snapshot_fields, util};
+
+#[derive(Default, Clone, Copy)]
+struct Tone {
+    counter: u16,
+    output: bool,
+}
+
+snapshot_fields!(Tone { counter, output });
+
+/// The Sunsoft 5B, a variant of the Yamaha YM2149F (itself a clone of the AY-3-8910) with three
+/// square wave channels, a noise generator and an envelope generator.
+/// https://www.nesdev.org/wiki/Sunsoft_5B_audio
+pub struct Sunsoft5bAudio {
+    address: u8,
+    registers: [u8; 16],
+    volume_table: [f32; 32],
+
+    divider: u8,
+    tones: [Tone; 3],
+
+    noise_counter: u16,
+    noise_shift: u32,
+
+    envelope_counter: u16,
+    envelope_step: u8,
+    envelope_attack: bool,
+    envelope_holding: bool,
+}
+
+snapshot_fields!(Sunsoft5bAudio {
+    address,
+    registers,
+    divider,
+    tones,
+    noise_counter,
+    noise_shift,
+    envelope_counter,
+    envelope_step,
+    envelope_attack,
+    envelope_holding,
+});
+
+impl Default for Sunsoft5bAudio {
+    fn default() -> Self {
+        let mut volume_table = [0.0; 32];
+        // Every step is 1.5 dB
+        for (level, volume) in volume_table.iter_mut().enumerate().skip(1) {
+            *volume = 10f32.powf(-((31 - level) as f32 * 1.5) / 20.0);
+        }
+
+        Self {
+            address: 0,
+            registers: [0; 16],
+            volume_table,
+            divider: 0,
+            tones: [Tone::default(); 3],
+            noise_counter: 0,
+            noise_shift: 1,
+            envelope_counter: 0,
+            envelope_step: 0,
+            envelope_attack: false,
+            envelope_holding: true,
+        }
+    }
+}
+
+impl Sunsoft5bAudio {
+    /// The chip runs at half the CPU clock, and its counters at 1/8th of that
+    const DIVIDER_CYCLES: u8 = 16;
+
+    const NOISE_PERIOD: usize = 0x06;
+    const MIXER: usize = 0x07;
+    const VOLUME: usize = 0x08;
+    const ENVELOPE_PERIOD_LOW: usize = 0x0B;
+    const ENVELOPE_PERIOD_HIGH: usize = 0x0C;
+    const ENVELOPE_SHAPE: usize = 0x0D;
+
+    /// Full volume on a single channel, the chip is quite a bit louder than the APU
+    const LEVEL: f32 = 0.2;
+
+    /// The address port, at $C000-$DFFF
+    pub fn write_address(&mut self, data: u8) {
+        self.address = data;
+    }
+
+    /// The data port, at $E000-$FFFF
+    pub fn write_data(&mut self, data: u8) {
+        // The upper bits act as a chip select
+        if self.address & 0b1111_0000 != 0 {
+            return;
+        }
+
+        let register = self.address as usize;
+        self.registers[register] = data;
+        if register == Self::ENVELOPE_SHAPE {
+            self.envelope_step = 0;
+            self.envelope_counter = 0;
+            self.envelope_holding = false;
+            self.envelope_attack = util::nth_bit(data, 2);
+        }
+    }
+
+    /// Advance by a single CPU cycle
+    pub fn clock(&mut self) {
+        self.divider += 1;
+        if self.divider < Self::DIVIDER_CYCLES {
+            return;
+        }
+        self.divider = 0;
+
+        for (channel, tone) in self.tones.iter_mut().enumerate() {
+            let period = (self.registers[channel * 2] as u16
+                | ((self.registers[channel * 2 + 1] & 0b0000_1111) as u16) << 8)
+                .max(1);
+
+            tone.counter += 1;
+            if tone.counter >= period {
+                tone.counter = 0;
+                tone.output = !tone.output;
+            }
+        }
+
+        let noise_period = ((self.registers[Self::NOISE_PERIOD] & 0b0001_1111) as u16).max(1);
+        self.noise_counter += 1;
+        if self.noise_counter >= noise_period * 2 {
+            self.noise_counter = 0;
+            // 17-bit linear feedback shift register
+            let feedback = (self.noise_shift ^ (self.noise_shift >> 3)) & 1;
+            self.noise_shift = (self.noise_shift >> 1) | (feedback << 16);
+        }
+
+        let envelope_period = u16::from_le_bytes([
+            self.registers[Self::ENVELOPE_PERIOD_LOW],
+            self.registers[Self::ENVELOPE_PERIOD_HIGH],
+        ])
+        .max(1);
+        self.envelope_counter += 1;
+        if self.envelope_counter >= envelope_period {
+            self.envelope_counter = 0;
+            self.clock_envelope();
+        }
+    }
+
+    /// https://www.nesdev.org/wiki/Sunsoft_5B_audio#Envelope
+    fn clock_envelope(&mut self) {
+        if self.envelope_holding {
+            return;
+        }
+
+        if self.envelope_step < 31 {
+            self.envelope_step += 1;
+            return;
+        }
+
+        let shape = self.registers[Self::ENVELOPE_SHAPE];
+        let (continuing, alternate, hold) = (
+            util::nth_bit(shape, 3),
+            util::nth_bit(shape, 1),
+            util::nth_bit(shape, 0),
+        );
+
+        if !continuing {
+            // Stay silent after a single ramp
+            self.envelope_holding = true;
+            self.envelope_attack = false;
+        } else if hold {
+            self.envelope_holding = true;
+            if alternate {
+                self.envelope_attack = !self.envelope_attack;
+            }
+        } else {
+            self.envelope_step = 0;
+            if alternate {
+                self.envelope_attack = !self.envelope_attack;
+            }
+        }
+    }
+
+    fn envelope_level(&self) -> u8 {
+        if self.envelope_attack {
+            self.envelope_step
+        } else {
+            31 - self.envelope_step
+        }
+    }
+
+    fn channel_level(&self, channel: usize) -> u8 {
+        let volume = self.registers[Self::VOLUME + channel];
+        if util::nth_bit(volume, 4) {
+            self.envelope_level()
+        } else {
+            // The fixed volume has half the resolution of the envelope
+            match volume & 0b0000_1111 {
+                0 => 0,
+                volume => (volume * 2) + 1,
+            }
+        }
+    }
+
+    pub fn output(&self) -> f32 {
+        let mixer = self.registers[Self::MIXER];
+        let noise = self.noise_shift & 1 != 0;
+
+        let sum: f32 = (0..self.tones.len())
+            .filter(|&channel| {
+                let tone_disabled = util::nth_bit(mixer, channel as u8);
+                let noise_disabled = util::nth_bit(mixer, channel as u8 + 3);
+                (self.tones[channel].output || tone_disabled) && (noise || noise_disabled)
+            })
+            .map(|channel| self.volume_table[self.channel_level(channel) as usize])
+            .sum();
+
+        sum * Self::LEVEL
+    }
+}
@@ -0,0 +1,189 @@
+use crate::{savestate::snapshot_fields, util};
+
+/// https://www.nesdev.org/wiki/VRC6_audio#Pulse_Channels
+#[derive(Default)]
+struct Vrc6Pulse {
+    constant: bool,
+    duty: u8,
+    volume: u8,
+    enabled: bool,
+    period: u16,
+    counter: u16,
+    step: u8,
+}
+
+snapshot_fields!(Vrc6Pulse {
+    constant,
+    duty,
+    volume,
+    enabled,
+    period,
+    counter,
+    step
+});
+
+impl Vrc6Pulse {
+    fn write_register(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.constant = util::nth_bit(data, 7);
+                self.duty = (data >> 4) & 0b0000_0111;
+                self.volume = data & 0b0000_1111;
+            }
+            1 => self.period = (self.period & 0x0F00) | data as u16,
+            2 => {
+                self.period = (self.period & 0x00FF) | (((data & 0b0000_1111) as u16) << 8);
+                self.enabled = util::nth_bit(data, 7);
+                if !self.enabled {
+                    self.step = 15;
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+
+        if self.counter == 0 {
+            self.counter = self.period >> shift;
+            self.step = self.step.wrapping_sub(1) & 0b0000_1111;
+        } else {
+            self.counter -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && (self.constant || self.step <= self.duty) {
+            self.volume
+        } else {
+            0
+        }
+    }
+}
+
+/// https://www.nesdev.org/wiki/VRC6_audio#Sawtooth_Channel
+#[derive(Default)]
+struct Vrc6Sawtooth {
+    rate: u8,
+    enabled: bool,
+    period: u16,
+    counter: u16,
+    step: u8,
+    accumulator: u8,
+}
+
+snapshot_fields!(Vrc6Sawtooth {
+    rate,
+    enabled,
+    period,
+    counter,
+    step,
+    accumulator
+});
+
+impl Vrc6Sawtooth {
+    fn write_register(&mut self, register: u16, data: u8) {
+        match register {
+            0 => self.rate = data & 0b0011_1111,
+            1 => self.period = (self.period & 0x0F00) | data as u16,
+            2 => {
+                self.period = (self.period & 0x00FF) | (((data & 0b0000_1111) as u16) << 8);
+                self.enabled = util::nth_bit(data, 7);
+                if !self.enabled {
+                    self.step = 0;
+                    self.accumulator = 0;
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+
+        if self.counter == 0 {
+            self.counter = self.period >> shift;
+
+            // The rate is added every other step, the accumulator resets after seven of them
+            self.step += 1;
+            if self.step == 14 {
+                self.step = 0;
+                self.accumulator = 0;
+            } else if self.step.is_multiple_of(2) {
+                self.accumulator = self.accumulator.wrapping_add(self.rate);
+            }
+        } else {
+            self.counter -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        self.accumulator >> 3
+    }
+}
+
+/// The sound channels of Konami's VRC6, two pulse and one sawtooth channel.
+/// https://www.nesdev.org/wiki/VRC6_audio
+#[derive(Default)]
+pub struct Vrc6Audio {
+    pulse_1: Vrc6Pulse,
+    pulse_2: Vrc6Pulse,
+    sawtooth: Vrc6Sawtooth,
+    halted: bool,
+    frequency_shift: u8,
+}
+
+snapshot_fields!(Vrc6Audio {
+    pulse_1,
+    pulse_2,
+    sawtooth,
+    halted,
+    frequency_shift
+});
+
+impl Vrc6Audio {
+    /// A full volume pulse is about as loud as one of the APU's
+    const LEVEL: f32 = 0.0099;
+
+    /// Write to one of the audio registers, at $9000-$B002 of the VRC6a address layout
+    pub fn write_register(&mut self, address: u16, data: u8) {
+        let register = address & 0b0011;
+        match address {
+            0x9003 => {
+                self.halted = util::nth_bit(data, 0);
+                self.frequency_shift = if util::nth_bit(data, 2) {
+                    8
+                } else if util::nth_bit(data, 1) {
+                    4
+                } else {
+                    0
+                };
+            }
+            0x9000..=0x9002 => self.pulse_1.write_register(register, data),
+            0xA000..=0xA002 => self.pulse_2.write_register(register, data),
+            0xB000..=0xB002 => self.sawtooth.write_register(register, data),
+            _ => tracing::error!("invalid VRC6 audio register write at ${:04X}", address),
+        }
+    }
+
+    /// Advance by a single CPU cycle
+    pub fn clock(&mut self) {
+        if self.halted {
+            return;
+        }
+
+        self.pulse_1.clock(self.frequency_shift);
+        self.pulse_2.clock(self.frequency_shift);
+        self.sawtooth.clock(self.frequency_shift);
+    }
+
+    pub fn output(&self) -> f32 {
+        let sum = self.pulse_1.output() + self.pulse_2.output() + self.sawtooth.output();
+        sum as f32 * Self::LEVEL
+    }
+}
@@ -0,0 +1,76 @@
+use std::f32::consts::PI;
+
+/// A first-order IIR filter
+pub enum Filter {
+    HighPass {
+        alpha: f32,
+        previous_input: f32,
+        previous_output: f32,
+    },
+    LowPass {
+        alpha: f32,
+        previous_output: f32,
+    },
+}
+
+impl Filter {
+    pub fn high_pass(sample_rate: f32, cutoff: f32) -> Self {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        Self::HighPass {
+            alpha: rc / (rc + (1.0 / sample_rate)),
+            previous_input: 0.0,
+            previous_output: 0.0,
+        }
+    }
+
+    pub fn low_pass(sample_rate: f32, cutoff: f32) -> Self {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / sample_rate;
+        Self::LowPass {
+            alpha: dt / (rc + dt),
+            previous_output: 0.0,
+        }
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        match self {
+            Self::HighPass {
+                alpha,
+                previous_input,
+                previous_output,
+            } => {
+                *previous_output = *alpha * (*previous_output + input - *previous_input);
+                *previous_input = input;
+                *previous_output
+            }
+
+            Self::LowPass {
+                alpha,
+                previous_output,
+            } => {
+                *previous_output += *alpha * (input - *previous_output);
+                *previous_output
+            }
+        }
+    }
+}
+
+/// The filters applied by the NES' audio output circuitry.
+/// https://www.nesdev.org/wiki/APU_Mixer
+pub struct FilterChain([Filter; 3]);
+
+impl FilterChain {
+    pub fn new(sample_rate: f32) -> Self {
+        Self([
+            Filter::high_pass(sample_rate, 90.0),
+            Filter::high_pass(sample_rate, 440.0),
+            Filter::low_pass(sample_rate, 14000.0),
+        ])
+    }
+
+    pub fn process(&mut self, sample: f32) -> f32 {
+        self.0
+            .iter_mut()
+            .fold(sample, |sample, filter| filter.process(sample))
+    }
+}
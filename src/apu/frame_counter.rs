@@ -0,0 +1,152 @@
+use crate::{
+    region::Region,
+    savestate::{snapshot_fields, Snapshot, StateReader, StateWriter},
+    util,
+};
+
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    #[default]
+    FourStep,
+    FiveStep,
+}
+
+impl Snapshot for Mode {
+    fn save(&self, state: &mut StateWriter) {
+        (*self == Self::FiveStep).save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        *self = if state.read()? {
+            Self::FiveStep
+        } else {
+            Self::FourStep
+        };
+        Ok(())
+    }
+}
+
+/// Which units a frame counter step clocks
+#[derive(Default, Clone, Copy)]
+pub struct FrameClock {
+    pub quarter: bool,
+    pub half: bool,
+}
+
+/// When the steps happen, in CPU cycles. See the tables on the wiki page.
+struct Timing {
+    quarter_1: usize,
+    half_1: usize,
+    quarter_3: usize,
+    four_step_last: usize,
+    four_step_len: usize,
+    five_step_last: usize,
+    five_step_len: usize,
+}
+
+impl Timing {
+    const NTSC: Self = Self {
+        quarter_1: 7457,
+        half_1: 14913,
+        quarter_3: 22371,
+        four_step_last: 29829,
+        four_step_len: 29830,
+        five_step_last: 37281,
+        five_step_len: 37282,
+    };
+
+    const PAL: Self = Self {
+        quarter_1: 8313,
+        half_1: 16627,
+        quarter_3: 24939,
+        four_step_last: 33253,
+        four_step_len: 33254,
+        five_step_last: 41565,
+        five_step_len: 41566,
+    };
+}
+
+/// https://www.nesdev.org/wiki/APU_Frame_Counter
+#[derive(Default)]
+pub struct FrameCounter {
+    mode: Mode,
+    interrupt_inhibit: bool,
+    pub interrupt_flag: bool,
+    cycles: usize,
+    pub region: Region,
+}
+
+snapshot_fields!(FrameCounter {
+    mode,
+    interrupt_inhibit,
+    interrupt_flag,
+    cycles
+});
+
+impl FrameCounter {
+    const fn timing(&self) -> &'static Timing {
+        if self.region.pal_apu() {
+            &Timing::PAL
+        } else {
+            &Timing::NTSC
+        }
+    }
+
+    /// Write to $4017. Returns the units to clock immediately, which happens in 5-step mode.
+    pub fn write(&mut self, data: u8) -> FrameClock {
+        self.mode = if util::nth_bit(data, 7) {
+            Mode::FiveStep
+        } else {
+            Mode::FourStep
+        };
+
+        self.interrupt_inhibit = util::nth_bit(data, 6);
+        if self.interrupt_inhibit {
+            self.interrupt_flag = false;
+        }
+
+        self.cycles = 0;
+        FrameClock {
+            quarter: self.mode == Mode::FiveStep,
+            half: self.mode == Mode::FiveStep,
+        }
+    }
+
+    /// Advance by a single CPU cycle
+    pub fn clock(&mut self) -> FrameClock {
+        self.cycles += 1;
+
+        let timing = self.timing();
+        let (last, len) = match self.mode {
+            Mode::FourStep => (timing.four_step_last, timing.four_step_len),
+            Mode::FiveStep => (timing.five_step_last, timing.five_step_len),
+        };
+
+        let clock = if self.cycles == timing.quarter_1 || self.cycles == timing.quarter_3 {
+            FrameClock {
+                quarter: true,
+                half: false,
+            }
+        } else if self.cycles == timing.half_1 || self.cycles == last {
+            FrameClock {
+                quarter: true,
+                half: true,
+            }
+        } else {
+            FrameClock::default()
+        };
+
+        if self.mode == Mode::FourStep
+            && !self.interrupt_inhibit
+            && (last - 1..=len).contains(&self.cycles)
+        {
+            self.interrupt_flag = true;
+        }
+
+        if self.cycles >= len {
+            self.cycles = 0;
+        }
+
+        clock
+    }
+}
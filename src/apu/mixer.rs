@@ -0,0 +1,136 @@
+use std::{fmt, sync::mpsc::Receiver};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioChannel {
+    Pulse1,
+    Pulse2,
+    Triangle,
+    Noise,
+    DeltaModulation,
+    Expansion,
+}
+
+impl AudioChannel {
+    pub const ALL: [Self; 6] = [
+        Self::Pulse1,
+        Self::Pulse2,
+        Self::Triangle,
+        Self::Noise,
+        Self::DeltaModulation,
+        Self::Expansion,
+    ];
+}
+
+impl fmt::Display for AudioChannel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pulse1 => write!(f, "Pulse 1"),
+            Self::Pulse2 => write!(f, "Pulse 2"),
+            Self::Triangle => write!(f, "Triangle"),
+            Self::Noise => write!(f, "Noise"),
+            Self::DeltaModulation => write!(f, "DMC"),
+            Self::Expansion => write!(f, "Expansion"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelSettings {
+    pub muted: bool,
+    pub solo: bool,
+    pub volume: f32,
+}
+
+impl Default for ChannelSettings {
+    fn default() -> Self {
+        Self {
+            muted: false,
+            solo: false,
+            volume: 1.0,
+        }
+    }
+}
+
+pub enum AudioRequest {
+    Mute(AudioChannel, bool),
+    Solo(AudioChannel, bool),
+    Volume(AudioChannel, f32),
+}
+
+/// Per-channel volume control, configured through requests from the GUI
+pub struct Mixer {
+    receiver: Option<Receiver<AudioRequest>>,
+    channels: [ChannelSettings; AudioChannel::ALL.len()],
+    // Cached since they are needed every cycle
+    gains: [f32; AudioChannel::ALL.len()],
+}
+
+impl Mixer {
+    pub fn new(receiver: Option<Receiver<AudioRequest>>) -> Self {
+        Self {
+            receiver,
+            channels: [ChannelSettings::default(); AudioChannel::ALL.len()],
+            gains: [1.0; AudioChannel::ALL.len()],
+        }
+    }
+
+    pub fn update(&mut self) {
+        let Some(receiver) = &self.receiver else {
+            return;
+        };
+
+        while let Ok(request) = receiver.try_recv() {
+            match request {
+                AudioRequest::Mute(channel, muted) => self.channels[channel as usize].muted = muted,
+                AudioRequest::Solo(channel, solo) => self.channels[channel as usize].solo = solo,
+                AudioRequest::Volume(channel, volume) => {
+                    self.channels[channel as usize].volume = volume
+                }
+            }
+
+            // When any channel is soloed, all others are silenced
+            let any_solo = self.channels.iter().any(|settings| settings.solo);
+            for (gain, settings) in self.gains.iter_mut().zip(self.channels.iter()) {
+                *gain = if (any_solo && !settings.solo) || (!any_solo && settings.muted) {
+                    0.0
+                } else {
+                    settings.volume
+                };
+            }
+        }
+    }
+
+    /// The factor to multiply a channel's output with
+    pub fn gain(&self, channel: AudioChannel) -> f32 {
+        self.gains[channel as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn solo_overrides_mute() {
+        let (sender, receiver) = channel();
+        let mut mixer = Mixer::new(Some(receiver));
+
+        sender
+            .send(AudioRequest::Mute(AudioChannel::Noise, true))
+            .unwrap();
+        sender
+            .send(AudioRequest::Volume(AudioChannel::Triangle, 0.5))
+            .unwrap();
+        mixer.update();
+        assert_eq!(mixer.gain(AudioChannel::Noise), 0.0);
+        assert_eq!(mixer.gain(AudioChannel::Triangle), 0.5);
+
+        sender
+            .send(AudioRequest::Solo(AudioChannel::Noise, true))
+            .unwrap();
+        mixer.update();
+        assert_eq!(mixer.gain(AudioChannel::Noise), 1.0);
+        assert_eq!(mixer.gain(AudioChannel::Triangle), 0.0);
+    }
+}
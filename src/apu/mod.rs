@@ -0,0 +1,377 @@
+mod dmc;
+pub mod expansion;
+mod filter;
+mod frame_counter;
+pub mod mixer;
+mod noise;
+mod pulse;
+mod triangle;
+mod units;
+pub mod wav;
+
+use {
+    self::{
+        dmc::DeltaModulation,
+        filter::FilterChain,
+        frame_counter::{FrameClock, FrameCounter},
+        mixer::{AudioChannel, AudioRequest, Mixer},
+        noise::Noise,
+        pulse::{Pulse, PulseChannel},
+        triangle::Triangle,
+    },
+    crate::{
+        bus::{Clock, CycleCount, Device},
+        region::Region,
+        savestate::snapshot_fields,
+        util,
+    },
+    std::sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{Receiver, Sender},
+    },
+};
+
+pub type SampleReceiver = Receiver<Vec<f32>>;
+pub type SampleSender = Sender<Vec<f32>>;
+
+/// The rate at which mixed samples are produced, in Hz
+pub const SAMPLE_RATE: u32 = 44100;
+
+/// The amount of samples the audio backend should keep buffered, about 50 milliseconds
+pub const SAMPLE_BUFFER_TARGET: usize = SAMPLE_RATE as usize / 20;
+
+/// How many samples are collected before sending them off, to avoid flooding the channel
+const SAMPLE_BATCH_LEN: usize = 256;
+
+/// The maximum amount the sample rate is allowed to deviate for dynamic rate control
+const MAX_RATE_DEVIATION: f64 = 0.005;
+
+/// The fill level of the audio backend's buffer, shared with the emulator so that it can pace itself
+#[derive(Default)]
+pub struct SampleBufferLevel {
+    enabled: AtomicBool,
+    buffered: AtomicUsize,
+}
+
+impl SampleBufferLevel {
+    pub fn enable(&self) {
+        self.enabled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn disable(&self) {
+        self.enabled.store(false, Ordering::Relaxed);
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn set(&self, buffered: usize) {
+        self.buffered.store(buffered, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> usize {
+        self.buffered.load(Ordering::Relaxed)
+    }
+}
+
+/// https://www.nesdev.org/wiki/APU
+pub struct Apu {
+    span: tracing::Span,
+
+    pulse_1: Pulse,
+    pulse_2: Pulse,
+    triangle: Triangle,
+    noise: Noise,
+    pub dmc: DeltaModulation,
+    frame_counter: FrameCounter,
+    pub mixer: Mixer,
+    /// The output of the cartridge's sound chip, if it has one
+    pub expansion_output: f32,
+
+    /// Pulse channels are clocked every other CPU cycle
+    even_cycle: bool,
+    region: Region,
+
+    filters: FilterChain,
+    cycles_per_sample: f64,
+    sample_cycles: f64,
+    sample_sum: f32,
+    sample_count: u32,
+    samples: Vec<f32>,
+    sample_sender: Option<SampleSender>,
+}
+
+snapshot_fields!(Apu {
+    pulse_1,
+    pulse_2,
+    triangle,
+    noise,
+    dmc,
+    frame_counter,
+    even_cycle
+});
+
+impl Apu {
+    const STATUS: u16 = 0x4015;
+    const FRAME_COUNTER: u16 = 0x4017;
+
+    pub fn new(
+        sample_sender: Option<SampleSender>,
+        audio_receiver: Option<Receiver<AudioRequest>>,
+    ) -> Self {
+        Self::with_mixer(sample_sender, Mixer::new(audio_receiver))
+    }
+
+    fn with_mixer(sample_sender: Option<SampleSender>, mixer: Mixer) -> Self {
+        Self {
+            span: tracing::span!(tracing::Level::INFO, "apu"),
+
+            pulse_1: Pulse::new(PulseChannel::One),
+            pulse_2: Pulse::new(PulseChannel::Two),
+            triangle: Triangle::default(),
+            noise: Noise::default(),
+            dmc: DeltaModulation::default(),
+            frame_counter: FrameCounter::default(),
+            mixer,
+            expansion_output: 0.0,
+
+            even_cycle: false,
+            region: Region::default(),
+
+            filters: FilterChain::new(SAMPLE_RATE as f32),
+            cycles_per_sample: Region::default().cpu_clock_rate() / SAMPLE_RATE as f64,
+            sample_cycles: 0.0,
+            sample_sum: 0.0,
+            sample_count: 0,
+            samples: Vec::with_capacity(SAMPLE_BATCH_LEN),
+            sample_sender,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        let mixer = std::mem::replace(&mut self.mixer, Mixer::new(None));
+        let region = self.region;
+        *self = Self::with_mixer(self.sample_sender.take(), mixer);
+        self.set_region(region);
+    }
+
+    /// Switch to the clock rate and period tables of a region
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.noise.region = region;
+        self.dmc.region = region;
+        self.frame_counter.region = region;
+        self.cycles_per_sample = region.cpu_clock_rate() / SAMPLE_RATE as f64;
+    }
+
+    pub const fn frame_interrupt(&self) -> bool {
+        self.frame_counter.interrupt_flag
+    }
+
+    /// Dynamic rate control, slightly adjusts the amount of samples produced based on how full
+    /// the audio backend's buffer is to avoid both underruns and ever-growing latency.
+    /// https://docs.libretro.com/development/cores/dynamic-rate-control/
+    pub fn adjust_sample_rate(&mut self, buffered: usize) {
+        let fill = (buffered as f64 / (SAMPLE_BUFFER_TARGET * 2) as f64).min(1.0);
+        let ratio = 1.0 + MAX_RATE_DEVIATION * (1.0 - 2.0 * fill);
+        self.cycles_per_sample = self.region.cpu_clock_rate() / (SAMPLE_RATE as f64 * ratio);
+    }
+
+    fn push_sample(&mut self, sample: f32) {
+        if self.sample_sender.is_none() {
+            return;
+        }
+
+        self.samples.push(sample);
+        if self.samples.len() >= SAMPLE_BATCH_LEN {
+            self.flush_samples();
+        }
+    }
+
+    /// Send all pending samples, even if there are not enough to fill a batch
+    pub fn flush_samples(&mut self) {
+        let Some(sender) = &self.sample_sender else {
+            return;
+        };
+
+        let samples = std::mem::replace(&mut self.samples, Vec::with_capacity(SAMPLE_BATCH_LEN));
+        if let Err(err) = sender.send(samples) {
+            tracing::error!("failed to send samples, disabling audio: {err}");
+            self.sample_sender = None;
+        }
+    }
+
+    #[tracing::instrument(skip(self), parent = &self.span)]
+    pub fn read_register(&mut self, address: u16) -> u8 {
+        if address != Self::STATUS {
+            tracing::trace!("reading write-only APU register at ${:04X}", address);
+            return 0;
+        }
+
+        let result = (self.pulse_1.length_counter.active() as u8)
+            | (self.pulse_2.length_counter.active() as u8) << 1
+            | (self.triangle.length_counter.active() as u8) << 2
+            | (self.noise.length_counter.active() as u8) << 3
+            | (self.dmc.active() as u8) << 4
+            | (self.frame_counter.interrupt_flag as u8) << 6
+            | (self.dmc.interrupt_flag as u8) << 7;
+
+        // Reading the status register acknowledges the frame interrupt
+        self.frame_counter.interrupt_flag = false;
+        tracing::trace!("status read: ${:02X}", result);
+        result
+    }
+
+    #[tracing::instrument(skip(self, address, data), parent = &self.span)]
+    pub fn write_register(&mut self, address: u16, data: u8) {
+        tracing::trace!("register write at ${:04X}: ${:02X}", address, data);
+        // Every channel has four registers
+        let register = address % 4;
+
+        match address {
+            0x4000..=0x4003 => self.pulse_1.write_register(register, data),
+            0x4004..=0x4007 => self.pulse_2.write_register(register, data),
+            0x4008..=0x400B => self.triangle.write_register(register, data),
+            0x400C..=0x400F => self.noise.write_register(register, data),
+            0x4010..=0x4013 => self.dmc.write_register(register, data),
+
+            Self::STATUS => {
+                self.pulse_1
+                    .length_counter
+                    .set_enabled(util::nth_bit(data, 0));
+                self.pulse_2
+                    .length_counter
+                    .set_enabled(util::nth_bit(data, 1));
+                self.triangle
+                    .length_counter
+                    .set_enabled(util::nth_bit(data, 2));
+                self.noise
+                    .length_counter
+                    .set_enabled(util::nth_bit(data, 3));
+                self.dmc.set_enabled(util::nth_bit(data, 4));
+            }
+
+            Self::FRAME_COUNTER => {
+                let clock = self.frame_counter.write(data);
+                self.clock_frame(clock);
+            }
+
+            _ => tracing::error!("invalid APU register write at ${:04X}", address),
+        }
+    }
+
+    fn clock_frame(&mut self, clock: FrameClock) {
+        if clock.quarter {
+            self.pulse_1.clock_quarter_frame();
+            self.pulse_2.clock_quarter_frame();
+            self.triangle.clock_quarter_frame();
+            self.noise.clock_quarter_frame();
+        }
+
+        if clock.half {
+            self.pulse_1.clock_half_frame();
+            self.pulse_2.clock_half_frame();
+            self.triangle.clock_half_frame();
+            self.noise.clock_half_frame();
+        }
+    }
+
+    /// https://www.nesdev.org/wiki/APU_Mixer#Emulation
+    fn mix(&self) -> f32 {
+        let gain = |channel| self.mixer.gain(channel);
+
+        let pulse = (self.pulse_1.output() as f32 * gain(AudioChannel::Pulse1))
+            + (self.pulse_2.output() as f32 * gain(AudioChannel::Pulse2));
+        let pulse_out = if pulse == 0.0 {
+            0.0
+        } else {
+            95.88 / ((8128.0 / pulse) + 100.0)
+        };
+
+        let tnd = (self.triangle.output() as f32 * gain(AudioChannel::Triangle) / 8227.0)
+            + (self.noise.output() as f32 * gain(AudioChannel::Noise) / 12241.0)
+            + (self.dmc.output() as f32 * gain(AudioChannel::DeltaModulation) / 22638.0);
+        let tnd_out = if tnd == 0.0 {
+            0.0
+        } else {
+            159.79 / ((1.0 / tnd) + 100.0)
+        };
+
+        pulse_out + tnd_out + (self.expansion_output * gain(AudioChannel::Expansion))
+    }
+
+    /// Advance by a single CPU cycle
+    fn step(&mut self) {
+        let clock = self.frame_counter.clock();
+        self.clock_frame(clock);
+
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+        if self.even_cycle {
+            self.pulse_1.clock_timer();
+            self.pulse_2.clock_timer();
+        }
+        self.even_cycle = !self.even_cycle;
+
+        // Average every cycle into one sample, which doubles as a crude low-pass filter
+        self.sample_sum += self.mix();
+        self.sample_count += 1;
+        self.sample_cycles += 1.0;
+
+        if self.sample_cycles >= self.cycles_per_sample {
+            self.sample_cycles -= self.cycles_per_sample;
+            let sample = self.sample_sum / self.sample_count as f32;
+            let sample = self.filters.process(sample);
+            self.push_sample(sample);
+            self.sample_sum = 0.0;
+            self.sample_count = 0;
+        }
+    }
+}
+
+impl Device for Apu {
+    fn contains(&self, address: u16) -> bool {
+        (0x4000..=0x4013).contains(&address)
+            || address == Self::STATUS
+            || address == Self::FRAME_COUNTER
+    }
+}
+
+impl Clock for Apu {
+    #[tracing::instrument(skip(self, cycles), parent = &self.span)]
+    fn tick_impl(&mut self, cycles: CycleCount) {
+        for _ in 0..cycles {
+            self.step();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn length_counter_status() {
+        let mut apu = Apu::new(None, None);
+        apu.write_register(0x4015, 0b0000_0001);
+        apu.write_register(0x4000, 0b1011_1111);
+        apu.write_register(0x4002, 0xFF);
+        apu.write_register(0x4003, 0b0000_1000);
+        assert_eq!(apu.read_register(0x4015) & 0b0001_1111, 0b0000_0001);
+
+        // Disabling the channel clears its length counter
+        apu.write_register(0x4015, 0);
+        assert_eq!(apu.read_register(0x4015) & 0b0001_1111, 0);
+    }
+
+    #[test]
+    fn frame_interrupt() {
+        let mut apu = Apu::new(None, None);
+        apu.tick(30_000);
+        assert!(util::nth_bit(apu.read_register(0x4015), 6));
+        // Reading the status acknowledges it
+        assert!(!util::nth_bit(apu.read_register(0x4015), 6));
+    }
+}
@@ -0,0 +1,93 @@
+use super::units::{Envelope, LengthCounter, Timer};
+use crate::{region::Region, savestate::snapshot_fields, util};
+
+/// https://www.nesdev.org/wiki/APU_Noise
+pub struct Noise {
+    pub length_counter: LengthCounter,
+    envelope: Envelope,
+    timer: Timer,
+    short_mode: bool,
+    shift_register: u16,
+    pub region: Region,
+}
+
+snapshot_fields!(Noise {
+    length_counter,
+    envelope,
+    timer,
+    short_mode,
+    shift_register
+});
+
+impl Default for Noise {
+    fn default() -> Self {
+        Self {
+            length_counter: LengthCounter::default(),
+            envelope: Envelope::default(),
+            timer: Timer::default(),
+            short_mode: false,
+            // Loaded with 1 on power-up
+            shift_register: 1,
+            region: Region::default(),
+        }
+    }
+}
+
+impl Noise {
+    const PERIOD_TABLE: [u16; 16] = [
+        4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+    ];
+    const PERIOD_TABLE_PAL: [u16; 16] = [
+        4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+    ];
+
+    pub fn write_register(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.envelope.write(data);
+                self.length_counter.halted = self.envelope.looping;
+            }
+            1 => {} // Unused
+            2 => {
+                self.short_mode = util::nth_bit(data, 7);
+                // The timer counts down from the period to zero, hence the minus one
+                let table = if self.region.pal_apu() {
+                    &Self::PERIOD_TABLE_PAL
+                } else {
+                    &Self::PERIOD_TABLE
+                };
+                self.timer.period = table[(data & 0b0000_1111) as usize] - 1;
+            }
+            3 => {
+                self.length_counter.load(data);
+                self.envelope.restart();
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// Clocked every CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer.clock() {
+            let tap = if self.short_mode { 6 } else { 1 };
+            let feedback = (self.shift_register & 1) ^ ((self.shift_register >> tap) & 1);
+            self.shift_register = (self.shift_register >> 1) | (feedback << 14);
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.shift_register & 1 == 1 || !self.length_counter.active() {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
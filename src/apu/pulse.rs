@@ -0,0 +1,152 @@
+use super::units::{Envelope, LengthCounter, Timer};
+use crate::{savestate::snapshot_fields, util};
+
+/// https://www.nesdev.org/wiki/APU_Sweep
+#[derive(Default)]
+struct Sweep {
+    enabled: bool,
+    negate: bool,
+    reload: bool,
+    period: u8,
+    shift: u8,
+    divider: u8,
+}
+
+snapshot_fields!(Sweep {
+    enabled,
+    negate,
+    reload,
+    period,
+    shift,
+    divider
+});
+
+impl Sweep {
+    fn write(&mut self, data: u8) {
+        self.enabled = util::nth_bit(data, 7);
+        self.period = (data >> 4) & 0b0000_0111;
+        self.negate = util::nth_bit(data, 3);
+        self.shift = data & 0b0000_0111;
+        self.reload = true;
+    }
+}
+
+/// Which of the two pulse channels this is, they differ slightly in how the sweep unit negates
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum PulseChannel {
+    One,
+    Two,
+}
+
+/// https://www.nesdev.org/wiki/APU_Pulse
+pub struct Pulse {
+    channel: PulseChannel,
+    pub length_counter: LengthCounter,
+    envelope: Envelope,
+    sweep: Sweep,
+    timer: Timer,
+    duty: u8,
+    sequence_step: u8,
+}
+
+snapshot_fields!(Pulse {
+    length_counter,
+    envelope,
+    sweep,
+    timer,
+    duty,
+    sequence_step
+});
+
+impl Pulse {
+    const DUTY_TABLE: [[u8; 8]; 4] = [
+        [0, 1, 0, 0, 0, 0, 0, 0],
+        [0, 1, 1, 0, 0, 0, 0, 0],
+        [0, 1, 1, 1, 1, 0, 0, 0],
+        [1, 0, 0, 1, 1, 1, 1, 1],
+    ];
+
+    pub fn new(channel: PulseChannel) -> Self {
+        Self {
+            channel,
+            length_counter: LengthCounter::default(),
+            envelope: Envelope::default(),
+            sweep: Sweep::default(),
+            timer: Timer::default(),
+            duty: 0,
+            sequence_step: 0,
+        }
+    }
+
+    pub fn write_register(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.duty = data >> 6;
+                self.envelope.write(data);
+                self.length_counter.halted = self.envelope.looping;
+            }
+            1 => self.sweep.write(data),
+            2 => self.timer.set_period_low(data),
+            3 => {
+                self.timer.set_period_high(data);
+                self.length_counter.load(data);
+                self.envelope.restart();
+                self.sequence_step = 0;
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn sweep_target_period(&self) -> u16 {
+        let period = self.timer.period;
+        let change = period >> self.sweep.shift;
+        if self.sweep.negate {
+            match self.channel {
+                // Pulse 1 uses one's complement, pulse 2 two's complement
+                PulseChannel::One => period.saturating_sub(change + 1),
+                PulseChannel::Two => period.saturating_sub(change),
+            }
+        } else {
+            period + change
+        }
+    }
+
+    fn muted(&self) -> bool {
+        self.timer.period < 8 || self.sweep_target_period() > 0x7FF
+    }
+
+    /// Clocked every other CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer.clock() {
+            self.sequence_step = (self.sequence_step + 1) % 8;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+
+        if self.sweep.divider == 0 && self.sweep.enabled && self.sweep.shift > 0 && !self.muted() {
+            self.timer.period = self.sweep_target_period();
+        }
+
+        if self.sweep.divider == 0 || self.sweep.reload {
+            self.sweep.divider = self.sweep.period;
+            self.sweep.reload = false;
+        } else {
+            self.sweep.divider -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        let sequence = Self::DUTY_TABLE[self.duty as usize][self.sequence_step as usize];
+        if sequence == 0 || self.muted() || !self.length_counter.active() {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
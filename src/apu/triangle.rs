@@ -0,0 +1,83 @@
+use super::units::{LengthCounter, Timer};
+use crate::{savestate::snapshot_fields, util};
+
+/// https://www.nesdev.org/wiki/APU_Triangle
+#[derive(Default)]
+pub struct Triangle {
+    pub length_counter: LengthCounter,
+    timer: Timer,
+    sequence_step: u8,
+
+    control: bool,
+    linear_counter: u8,
+    linear_counter_reload: u8,
+    linear_counter_reload_flag: bool,
+}
+
+snapshot_fields!(Triangle {
+    length_counter,
+    timer,
+    sequence_step,
+    control,
+    linear_counter,
+    linear_counter_reload,
+    linear_counter_reload_flag,
+});
+
+impl Triangle {
+    #[rustfmt::skip]
+    const SEQUENCE: [u8; 32] = [
+        15, 14, 13, 12, 11, 10,  9,  8,  7,  6,  5,  4,  3,  2,  1,  0,
+         0,  1,  2,  3,  4,  5,  6,  7,  8,  9, 10, 11, 12, 13, 14, 15,
+    ];
+
+    pub fn write_register(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.control = util::nth_bit(data, 7);
+                self.length_counter.halted = self.control;
+                self.linear_counter_reload = data & 0b0111_1111;
+            }
+            1 => {} // Unused
+            2 => self.timer.set_period_low(data),
+            3 => {
+                self.timer.set_period_high(data);
+                self.length_counter.load(data);
+                self.linear_counter_reload_flag = true;
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// Clocked every CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer.clock() && self.length_counter.active() && self.linear_counter > 0 {
+            self.sequence_step = (self.sequence_step + 1) % 32;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        if self.linear_counter_reload_flag {
+            self.linear_counter = self.linear_counter_reload;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+
+        if !self.control {
+            self.linear_counter_reload_flag = false;
+        }
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+    }
+
+    pub fn output(&self) -> u8 {
+        // Ultrasonic frequencies are silenced rather than emulated, they only cause popping
+        if self.timer.period < 2 {
+            7
+        } else {
+            Self::SEQUENCE[self.sequence_step as usize]
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use crate::{savestate::snapshot_fields, util};
+
+/// https://www.nesdev.org/wiki/APU_Envelope
+#[derive(Default)]
+pub struct Envelope {
+    start: bool,
+    divider: u8,
+    decay_level: u8,
+    pub looping: bool,
+    constant_volume: bool,
+    volume: u8,
+}
+
+snapshot_fields!(Envelope {
+    start,
+    divider,
+    decay_level,
+    looping,
+    constant_volume,
+    volume
+});
+
+impl Envelope {
+    /// Update the envelope from the lower 6 bits of a channel control register
+    pub fn write(&mut self, data: u8) {
+        self.looping = util::nth_bit(data, 5);
+        self.constant_volume = util::nth_bit(data, 4);
+        self.volume = data & 0b0000_1111;
+    }
+
+    pub fn restart(&mut self) {
+        self.start = true;
+    }
+
+    /// Clocked by the frame counter every quarter frame
+    pub fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay_level = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay_level > 0 {
+                self.decay_level -= 1;
+            } else if self.looping {
+                self.decay_level = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    pub const fn output(&self) -> u8 {
+        if self.constant_volume {
+            self.volume
+        } else {
+            self.decay_level
+        }
+    }
+}
+
+/// https://www.nesdev.org/wiki/APU_Length_Counter
+#[derive(Default)]
+pub struct LengthCounter {
+    enabled: bool,
+    pub halted: bool,
+    counter: u8,
+}
+
+snapshot_fields!(LengthCounter {
+    enabled,
+    halted,
+    counter
+});
+
+impl LengthCounter {
+    #[rustfmt::skip]
+    const TABLE: [u8; 32] = [
+        10, 254, 20,  2, 40,  4, 80,  6, 160,  8, 60, 10, 14, 12, 26, 14,
+        12,  16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30,
+    ];
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.counter = 0;
+        }
+    }
+
+    /// Load the counter from the upper 5 bits of a length counter register
+    pub fn load(&mut self, data: u8) {
+        if self.enabled {
+            self.counter = Self::TABLE[(data >> 3) as usize];
+        }
+    }
+
+    /// Clocked by the frame counter every half frame
+    pub fn clock(&mut self) {
+        if !self.halted && self.counter > 0 {
+            self.counter -= 1;
+        }
+    }
+
+    pub const fn active(&self) -> bool {
+        self.counter > 0
+    }
+}
+
+/// A divider that counts down from a period, reloading when it reaches zero
+#[derive(Default)]
+pub struct Timer {
+    pub period: u16,
+    counter: u16,
+}
+
+snapshot_fields!(Timer { period, counter });
+
+impl Timer {
+    pub fn set_period_low(&mut self, data: u8) {
+        self.period = (self.period & 0xFF00) | data as u16;
+    }
+
+    pub fn set_period_high(&mut self, data: u8) {
+        self.period = (self.period & 0x00FF) | (((data & 0b0000_0111) as u16) << 8);
+    }
+
+    /// Returns true when the timer has wrapped around, clocking whatever it drives
+    pub fn clock(&mut self) -> bool {
+        if self.counter == 0 {
+            self.counter = self.period;
+            true
+        } else {
+            self.counter -= 1;
+            false
+        }
+    }
+}
@@ -1,15 +1,20 @@
 use crate::{
-    cartridge::{Cartridge, MapperInstance},
+    apu::{self, mixer::AudioRequest, Apu, SampleBufferLevel, SampleSender},
+    cartridge::{rom::Rom, Cartridge, CartridgeError, Mapper, MapperInstance},
     cheat::CheatReceiver,
     controller::{self, Controller},
     cpu::CpuRam,
-    ppu::{self, renderer::PixelBuffer, Ppu},
+    dma::Dma,
+    ppu::{self, PixelSender, Ppu, VideoRequest},
+    region::Region,
+    savestate::{Snapshot, StateReader, StateWriter},
 };
 use std::{
     cell::RefCell,
+    fs, io,
     path::PathBuf,
     rc::Rc,
-    sync::mpsc::{Receiver, Sender},
+    sync::{mpsc::Receiver, Arc},
     time,
 };
 
@@ -38,16 +43,59 @@ pub trait Memory {
     fn read_word(&mut self, address: u16) -> u16 {
         u16::from_le_bytes([self.read_byte(address), self.read_byte(address + 1)])
     }
+}
+
+pub trait Device {
+    fn contains(&self, address: u16) -> bool;
+}
+
+/// A device that can pull the shared IRQ line
+#[derive(Debug, Clone, Copy)]
+pub enum InterruptSource {
+    FrameCounter,
+    DeltaModulation,
+    Mapper,
+}
 
-    fn write_word(&mut self, address: u16, data: u16) {
-        for (i, val) in u16::to_le_bytes(data).iter().enumerate() {
-            self.write_byte(address + i as u16, *val);
+/// The IRQ line shared between all devices, it stays asserted for as long as any source holds it.
+/// https://www.nesdev.org/wiki/IRQ
+#[derive(Default)]
+pub struct InterruptLine(u8);
+
+impl InterruptLine {
+    const fn mask(source: InterruptSource) -> u8 {
+        1 << source as u8
+    }
+
+    pub fn assert(&mut self, source: InterruptSource) {
+        self.0 |= Self::mask(source);
+    }
+
+    pub fn acknowledge(&mut self, source: InterruptSource) {
+        self.0 &= !Self::mask(source);
+    }
+
+    pub fn set(&mut self, source: InterruptSource, asserted: bool) {
+        if asserted {
+            self.assert(source);
+        } else {
+            self.acknowledge(source);
         }
     }
+
+    pub const fn asserted(&self) -> bool {
+        self.0 != 0
+    }
 }
 
-pub trait Device {
-    fn contains(&self, address: u16) -> bool;
+impl Snapshot for InterruptLine {
+    fn save(&self, state: &mut StateWriter) {
+        self.0.save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.0.load(state)
+    }
 }
 
 pub struct Bus {
@@ -55,63 +103,336 @@ pub struct Bus {
     pub mapper: Option<MapperInstance>,
     pub cpu_ram: CpuRam,
     pub cycles: CycleCount,
+    pub frames: usize,
     pub ppu: Ppu,
+    pub apu: Apu,
     pub controller: Controller,
+    pub interrupt: InterruptLine,
+    dma: Dma,
     time_since_last_frame: time::Instant,
+    /// The cycles of the current instruction before its last one, run as soon as it accesses a PPU
+    /// register so the access lands on the right dot
+    cycles_before_access: Option<CycleCount>,
+    /// Whether an NMI was detected before the last cycle of the current instruction
+    nmi_detected: bool,
+    /// The register the CPU is about to read while catching up, a DMA halting it reads it again
+    halted_read: Option<u16>,
+    pub throttle: bool,
+    sample_buffer_level: Arc<SampleBufferLevel>,
+
+    /// Where the loaded ROM came from, save states are stored next to it. Not known for ROMs that
+    /// were loaded from memory.
+    pub rom_path: Option<PathBuf>,
+    pub rom_checksum: u64,
+    /// The battery-backed RAM as it was last written to disk
+    saved_battery_ram: Vec<u8>,
+
+    region: Region,
+    /// Used instead of the region the cartridge asks for
+    pub region_override: Option<Region>,
 
-    rom_receiver: Receiver<PathBuf>,
     cheat_receiver: Option<CheatReceiver>,
 }
 
+/// The mapper has to be the same one the state was saved with, `savestate` makes sure the ROM matches
+impl Snapshot for Bus {
+    fn save(&self, state: &mut StateWriter) {
+        self.cpu_ram.save(state);
+        self.cycles.save(state);
+        self.frames.save(state);
+        self.interrupt.save(state);
+        self.ppu.save(state);
+        self.apu.save(state);
+        self.controller.save(state);
+        if let Some(mapper) = &self.mapper {
+            mapper.borrow().save(state);
+        }
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.cpu_ram.load(state)?;
+        self.cycles.load(state)?;
+        self.frames.load(state)?;
+        self.interrupt.load(state)?;
+        self.ppu.load(state)?;
+        self.apu.load(state)?;
+        self.controller.load(state)?;
+        if let Some(mapper) = &self.mapper {
+            mapper.borrow_mut().load(state)?;
+        }
+        Ok(())
+    }
+}
+
 impl Bus {
     const RESET_CYCLES: usize = 7;
+    /// How often battery-backed RAM is written to disk when it changed, every few seconds
+    const BATTERY_SAVE_FRAMES: usize = 300;
 
     pub fn new(
         button_receiver: Receiver<controller::Buttons>,
-        pixel_sender: Sender<Box<PixelBuffer>>,
-        rom_receiver: Receiver<PathBuf>,
+        pixel_sender: PixelSender,
+        (sample_sender, sample_buffer_level): (Option<SampleSender>, Arc<SampleBufferLevel>),
         cheat_receiver: Option<CheatReceiver>,
+        audio_receiver: Option<Receiver<AudioRequest>>,
+        video_receiver: Option<Receiver<VideoRequest>>,
     ) -> Bus {
         let span = tracing::span!(tracing::Level::INFO, "bus");
         tracing::info!("succesfully initialized");
         Bus {
             span,
-            rom_receiver,
             mapper: None,
-            ppu: Ppu::new(pixel_sender),
+            ppu: Ppu::new(pixel_sender, video_receiver),
+            apu: Apu::new(sample_sender, audio_receiver),
             cpu_ram: CpuRam::default(),
             cycles: 0,
+            frames: 0,
             controller: Controller::new(button_receiver),
+            interrupt: InterruptLine::default(),
+            dma: Dma::default(),
             time_since_last_frame: time::Instant::now(),
+            cycles_before_access: None,
+            nmi_detected: false,
+            halted_read: None,
+            throttle: true,
+            sample_buffer_level,
+            rom_path: None,
+            rom_checksum: 0,
+            saved_battery_ram: Vec::new(),
+            region: Region::default(),
+            region_override: None,
             cheat_receiver,
         }
     }
 
     pub fn reset(&mut self) {
         self.ppu.reset();
+        self.apu.reset();
         self.cpu_ram = CpuRam::default();
         self.cycles = Self::RESET_CYCLES;
+        self.frames = 0;
+        self.interrupt = InterruptLine::default();
         self.time_since_last_frame = time::Instant::now();
+        if let Some(mapper) = &self.mapper {
+            mapper.borrow_mut().reset();
+        }
     }
 
-    pub fn load_cartridge(&mut self, cartridge: Cartridge) {
-        let mapper = Rc::new(RefCell::new(cartridge.into()));
+    pub fn load_cartridge(&mut self, cartridge: Cartridge) -> Result<(), CartridgeError> {
+        let rom_checksum = cartridge.checksum();
+        let region = self.region_override.unwrap_or(cartridge.header.region);
+        let mut mapper: Box<dyn Mapper> = cartridge.try_into()?;
+        mapper.set_region(region);
+        let mapper = Rc::new(RefCell::new(mapper));
+
+        self.rom_checksum = rom_checksum;
+        self.set_region(region);
         self.mapper = Some(mapper.clone());
         self.ppu.load_mapper(mapper);
+        self.load_battery_ram();
+        Ok(())
+    }
+
+    /// Battery-backed RAM is stored next to the ROM, like save states
+    fn battery_path(&self) -> Option<PathBuf> {
+        self.rom_path
+            .as_ref()
+            .map(|path| path.with_extension("sav"))
+    }
+
+    fn load_battery_ram(&mut self) {
+        let (Some(mapper), Some(path)) = (self.mapper.clone(), self.battery_path()) else {
+            return;
+        };
+        let mut mapper = mapper.borrow_mut();
+        let Some(ram) = mapper.battery_ram() else {
+            return;
+        };
+
+        match fs::read(&path) {
+            Ok(data) => {
+                let len = data.len().min(ram.len());
+                ram[..len].copy_from_slice(&data[..len]);
+                tracing::info!("loaded battery-backed RAM from {}", path.display());
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => tracing::error!("failed to read {}: {err}", path.display()),
+        }
+        self.saved_battery_ram = ram.to_vec();
+    }
+
+    /// Write the battery-backed RAM to disk, if it changed since the last time
+    pub fn save_battery_ram(&mut self) {
+        let (Some(mapper), Some(path)) = (self.mapper.clone(), self.battery_path()) else {
+            return;
+        };
+        let mut mapper = mapper.borrow_mut();
+        let Some(ram) = mapper.battery_ram() else {
+            return;
+        };
+        if *ram == self.saved_battery_ram[..] {
+            return;
+        }
+
+        match fs::write(&path, &ram) {
+            Ok(()) => {
+                tracing::info!("saved battery-backed RAM to {}", path.display());
+                self.saved_battery_ram = ram.to_vec();
+            }
+            Err(err) => tracing::error!("failed to write {}: {err}", path.display()),
+        }
+    }
+
+    fn set_region(&mut self, region: Region) {
+        tracing::info!("running at {region} timing");
+        self.region = region;
+        self.ppu.region = region;
+        self.apu.set_region(region);
+    }
+
+    pub fn start_instruction(&mut self, cycles: CycleCount) {
+        self.cycles_before_access = Some(cycles.saturating_sub(1));
+        self.nmi_detected = false;
+    }
+
+    /// Run the cycles of the instruction up to its last one, unless that already happened.
+    /// `read` is the register that is read afterwards, if any.
+    fn catch_up(&mut self, read: Option<u16>) {
+        if let Some(cycles) = self.cycles_before_access.take() {
+            self.halted_read = read;
+            self.tick(cycles);
+            self.halted_read = None;
+            // Interrupts are polled before the last cycle, see https://www.nesdev.org/wiki/CPU_interrupts
+            self.nmi_detected = self.ppu.nmi_triggered();
+        }
+    }
+
+    /// Run the rest of the instruction, returns whether an NMI should follow it
+    pub fn finish_instruction(&mut self) -> bool {
+        self.catch_up(None);
+        // An access right as vblank starts can still cancel the NMI, otherwise it stays pending
+        // until after the next instruction
+        let nmi = self.nmi_detected && self.ppu.poll_nmi();
+        self.tick(1);
+        self.run_oam_dma();
+        nmi
+    }
+
+    /// Halt the CPU to copy a page into OAM, which takes 513 or 514 cycles
+    /// https://www.nesdev.org/wiki/DMA#OAM_DMA
+    fn run_oam_dma(&mut self) {
+        let Some(page) = self.dma.start_oam() else {
+            return;
+        };
+
+        // The halt cycle, followed by another one when the copy would start on a put cycle
+        self.tick(1);
+        self.tick_once_if(!Dma::is_get_cycle(self.cycles));
+        for address in self.ppu.oam.dma(page) {
+            let byte = self.read_byte(address as u16);
+            self.tick(1);
+            self.ppu.oam.write_data(byte);
+            self.tick(1);
+        }
+        self.dma.finish_oam();
+    }
+
+    /// Halt the CPU to fetch a sample for the DMC
+    /// https://www.nesdev.org/wiki/DMA#DMC_DMA
+    fn run_dmc_dma(&mut self, address: u16) {
+        self.dma.dmc_running = true;
+        let wait = self.dma.dmc_wait(self.cycles);
+        if let Some(read) = self.halted_read {
+            self.repeat_read(read, wait);
+        }
+        self.tick(wait);
+
+        let sample = self.read_byte(address);
+        self.apu.dmc.load_sample(sample);
+        self.tick(1);
+        self.dma.dmc_running = false;
+    }
+
+    /// The CPU keeps reading the address it was halted on, which matters for registers with side effects
+    fn repeat_read(&mut self, address: u16, cycles: CycleCount) {
+        if self.controller.contains(address) {
+            // Consecutive reads are seen as one, so the controller loses a single bit
+            self.controller.read();
+        } else if let Some(register) = ppu::registers::get_register(address) {
+            for _ in 0..cycles {
+                self.ppu.read_register(register);
+            }
+        }
     }
 
     pub fn unload_cartridge(&mut self) {
+        self.save_battery_ram();
         self.mapper = None;
+        self.rom_path = None;
         self.ppu.unload_mapper();
     }
 
-    pub fn has_cartridge(&mut self) -> bool {
-        if let Ok(path) = self.rom_receiver.try_recv() {
-            let cartridge = Cartridge::from(path);
-            self.load_cartridge(cartridge);
-        }
+    /// Swap the cartridge for the given ROM, saving the battery-backed RAM of the current one first.
+    /// The current cartridge is kept when the ROM can't be parsed.
+    pub fn load_rom(&mut self, rom: Rom) -> Result<(), CartridgeError> {
+        tracing::info!("loading ROM {}", rom.name);
+        let cartridge = Cartridge::from_bytes(&rom.data)?;
+        self.unload_cartridge();
+
+        // Set first so the battery-backed RAM is found
+        self.rom_path = rom.path;
+        self.load_cartridge(cartridge)
+            .inspect_err(|_| self.rom_path = None)
+    }
+
+    pub const fn has_cartridge(&self) -> bool {
         self.mapper.is_some()
     }
+
+    /// Switch tracks when playing a music file, takes effect after the next reset
+    pub fn select_track(&mut self, track: u8) {
+        if let Some(mapper) = &self.mapper {
+            mapper.borrow_mut().select_track(track);
+        }
+    }
+
+    /// Wait until it is time to emulate the next frame. When audio is playing its buffer dictates the pace,
+    /// otherwise we simply sleep for the remainder of the frame.
+    fn limit_frame_rate(&mut self) {
+        if self.sample_buffer_level.enabled() {
+            self.apu.adjust_sample_rate(self.sample_buffer_level.get());
+            while self.sample_buffer_level.enabled()
+                && self.sample_buffer_level.get() > apu::SAMPLE_BUFFER_TARGET
+            {
+                std::thread::sleep(time::Duration::from_millis(1));
+            }
+        } else if let Some(remaining) = self
+            .region
+            .frame_duration()
+            .checked_sub(self.time_since_last_frame.elapsed())
+        {
+            std::thread::sleep(remaining);
+        }
+
+        self.time_since_last_frame = time::Instant::now();
+    }
+
+    /// Check whether any device is asserting the IRQ line
+    pub fn poll_irq(&mut self) -> bool {
+        self.interrupt
+            .set(InterruptSource::FrameCounter, self.apu.frame_interrupt());
+        self.interrupt.set(
+            InterruptSource::DeltaModulation,
+            self.apu.dmc.interrupt_flag,
+        );
+        self.interrupt.set(
+            InterruptSource::Mapper,
+            self.mapper
+                .as_ref()
+                .is_some_and(|mapper| mapper.borrow().irq_pending()),
+        );
+        self.interrupt.asserted()
+    }
 }
 
 impl Memory for Bus {
@@ -125,27 +446,22 @@ impl Memory for Bus {
         }
 
         if self.controller.contains(address) {
+            self.catch_up(Some(address));
             self.controller.read()
         } else if self.cpu_ram.contains(address) {
             self.cpu_ram[address]
+        } else if self.apu.contains(address) {
+            self.apu.read_register(address)
         } else if self
             .mapper
             .as_ref()
-            .map_or(false, |c| c.borrow().contains(address))
+            .is_some_and(|c| c.borrow().contains(address))
         {
             self.mapper.as_mut().unwrap().borrow_mut().read_cpu(address)
-        } else if let Some((register, mutability)) = ppu::registers::get_register(address) {
-            if mutability.readable() {
-                tracing::trace!("PPU register {} read at ${:04X}", register, address);
-                self.ppu.read_register(register)
-            } else {
-                tracing::error!(
-                    "reading write-only PPU register {} at ${:04X}",
-                    register,
-                    address
-                );
-                0
-            }
+        } else if let Some(register) = ppu::registers::get_register(address) {
+            tracing::trace!("PPU register {} read at ${:04X}", register, address);
+            self.catch_up(Some(address));
+            self.ppu.read_register(register)
         } else {
             tracing::warn!("unimplemented read at ${:04X}", address);
             0
@@ -158,37 +474,29 @@ impl Memory for Bus {
             self.controller.write(data);
         } else if self.cpu_ram.contains(address) {
             self.cpu_ram[address] = data;
-        } else if let Some((register, mutability)) = ppu::registers::get_register(address) {
-            if mutability.writable() {
-                tracing::trace!(
-                    "PPU register {} write at ${:04X}: ${:02X}",
-                    register,
-                    address,
-                    data
-                );
-
-                // TODO: this isn't the prettiest, but we need special behavior from the bus for DMA
-                if register == &ppu::registers::Register::ObjectAttributeDirectMemoryAccess {
-                    let range = self.ppu.oam.dma(data);
-                    for i in range {
-                        let byte = self.read_byte(i.try_into().unwrap());
-                        self.ppu.oam.write_data(byte);
-                    }
-                } else {
-                    self.ppu.write_register(register, data);
-                }
+        } else if self.apu.contains(address) {
+            self.apu.write_register(address, data);
+        } else if let Some(register) = ppu::registers::get_register(address) {
+            tracing::trace!(
+                "PPU register {} write at ${:04X}: ${:02X}",
+                register,
+                address,
+                data
+            );
+
+            self.catch_up(None);
+
+            // TODO: this isn't the prettiest, but we need special behavior from the bus for DMA
+            if register == &ppu::registers::Register::ObjectAttributeDirectMemoryAccess {
+                // The copy starts once the instruction is done
+                self.dma.request_oam(data);
             } else {
-                tracing::error!(
-                    "writing read-only PPU register {} at ${:04X}",
-                    register,
-                    address
-                );
-                panic!()
+                self.ppu.write_register(register, data);
             }
         } else if self
             .mapper
             .as_ref()
-            .map_or(false, |c| c.borrow().contains(address))
+            .is_some_and(|c| c.borrow().contains(address))
         {
             self.mapper
                 .as_ref()
@@ -210,21 +518,34 @@ impl Clock for Bus {
         self.controller.update();
         self.cycles += cycles;
 
-        let vblank_before = self.ppu.status.vblank_started();
-        self.ppu.tick(cycles);
-        let vblank_after = self.ppu.status.vblank_started();
+        // Expansion audio is mixed by the APU, so the mapper has to run in lockstep with it
+        for _ in 0..cycles {
+            if let Some(mapper) = &self.mapper {
+                let mut mapper = mapper.borrow_mut();
+                mapper.clock(1);
+                self.apu.expansion_output = mapper.audio_output();
+            }
+            self.apu.tick(1);
+        }
 
-        // TODO: Would be nice to move this to ppu::tick()
-        if !vblank_before && vblank_after {
+        self.ppu.tick(cycles);
+        if self.ppu.poll_frame() {
             self.ppu.render();
+            self.ppu.update_settings();
+            self.apu.mixer.update();
+            self.frames += 1;
+            if self.frames.is_multiple_of(Self::BATTERY_SAVE_FRAMES) {
+                self.save_battery_ram();
+            }
+            if self.throttle {
+                self.limit_frame_rate();
+            }
+        }
 
-            // TODO: how accurate is this?
-            if self.time_since_last_frame.elapsed() < time::Duration::from_millis(16) {
-                std::thread::sleep(
-                    time::Duration::from_millis(16) - self.time_since_last_frame.elapsed(),
-                );
+        if !self.dma.dmc_running {
+            if let Some(address) = self.apu.dmc.sample_request() {
+                self.run_dmc_dma(address);
             }
-            self.time_since_last_frame = time::Instant::now();
         }
     }
 }
@@ -1,12 +1,23 @@
 mod cnrom;
+mod fme7;
 mod mmc1;
 mod mmc3;
+mod namco163;
 mod nrom;
+mod nsf;
 mod uxrom;
+mod vrc6;
 
-pub use super::{Cartridge, Mirroring, PROGRAM_ROM_PAGE_SIZE, PROGRAM_ROM_START};
+pub use super::{
+    Cartridge, CartridgeError, Mirroring, PROGRAM_RAM_PAGE_SIZE, PROGRAM_ROM_PAGE_SIZE,
+    PROGRAM_ROM_START,
+};
 use {
-    crate::bus::Device,
+    crate::{
+        bus::{CycleCount, Device},
+        region::Region,
+        savestate::Snapshot,
+    },
     std::{cell::RefCell, ops::Range, rc::Rc},
 };
 
@@ -14,7 +25,8 @@ use {
 // mutate it hence the RefCell. Trait objects are not sized, which a Box fixes.
 pub type MapperInstance = Rc<RefCell<Box<dyn Mapper>>>;
 
-pub trait Mapper {
+/// Mappers are part of save states, so they have to snapshot their registers and memory
+pub trait Mapper: Snapshot {
     fn mirroring(&self) -> Mirroring;
 
     fn read_cpu(&mut self, address: u16) -> u8;
@@ -27,6 +39,43 @@ pub trait Mapper {
         false
     }
 
+    /// The program RAM, if the cartridge keeps it alive with a battery so it can be saved to disk
+    fn battery_ram(&mut self) -> Option<&mut [u8]> {
+        None
+    }
+
+    /// Whether the mapper responds to the expansion area at $4020-$5FFF
+    fn has_expansion_area(&self) -> bool {
+        false
+    }
+
+    /// Whether the mapper is asserting the IRQ line
+    fn irq_pending(&self) -> bool {
+        false
+    }
+
+    /// Called by the PPU once per rendered scanline, at the point where the address line A12 rises
+    /// because it switches from fetching background to sprite patterns.
+    fn clock_scanline(&mut self) {}
+
+    /// Called by the bus after the CPU has spent the given amount of cycles, for mappers with timers
+    fn clock(&mut self, _cycles: CycleCount) {}
+
+    /// The current output of the cartridge's sound chip, sampled by the APU every CPU cycle
+    fn audio_output(&self) -> f32 {
+        0.0
+    }
+
+    /// Restore the power-on state of the registers, when the console is reset
+    fn reset(&mut self) {}
+
+    /// Called when the cartridge is loaded with the timing the console runs at, which is the
+    /// region of the cartridge unless it is overridden
+    fn set_region(&mut self, _region: Region) {}
+
+    /// Switch to a different track, only supported by music players
+    fn select_track(&mut self, _track: u8) {}
+
     fn read_cpu_range(&mut self, range: Range<usize>) -> Vec<u8> {
         range.map(|address| self.read_cpu(address as u16)).collect()
     }
@@ -53,7 +102,9 @@ where
     T: Mapper + ?Sized,
 {
     fn contains(&self, address: u16) -> bool {
-        let start = if self.has_program_ram() {
+        let start = if self.has_expansion_area() {
+            0x4020
+        } else if self.has_program_ram() {
             0x6000
         } else {
             PROGRAM_ROM_START
@@ -63,15 +114,25 @@ where
     }
 }
 
-impl From<Cartridge> for Box<dyn Mapper> {
-    fn from(cart: Cartridge) -> Self {
-        match cart.header.mapper_id {
+impl TryFrom<Cartridge> for Box<dyn Mapper> {
+    type Error = CartridgeError;
+
+    fn try_from(cart: Cartridge) -> Result<Self, Self::Error> {
+        if cart.nsf.is_some() {
+            return Ok(Box::new(nsf::NsfPlayer::new(cart)));
+        }
+
+        Ok(match cart.header.mapper_id {
             0 => Box::new(nrom::NROM::new(cart)),
             1 => Box::new(mmc1::MMC1::new(cart)),
             2 => Box::new(uxrom::UxROM::new(cart)),
             3 => Box::new(cnrom::CnROM::new(cart)),
             4 => Box::new(mmc3::MMC3::new(cart)),
-            _ => panic!("mapper {} not implemented", cart.header.mapper_id),
-        }
+            19 => Box::new(namco163::Namco163::new(cart)),
+            24 => Box::new(vrc6::VRC6::new(cart, false)),
+            26 => Box::new(vrc6::VRC6::new(cart, true)),
+            69 => Box::new(fme7::FME7::new(cart)),
+            mapper_id => return Err(CartridgeError::UnsupportedMapper(mapper_id)),
+        })
     }
 }
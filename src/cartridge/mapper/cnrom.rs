@@ -1,4 +1,5 @@
 use super::{Cartridge, Mapper, Mirroring, PROGRAM_ROM_START};
+use crate::savestate::snapshot_fields;
 
 /// https://www.nesdev.org/wiki/INES_Mapper_003
 pub struct CnROM {
@@ -15,6 +16,11 @@ impl CnROM {
     }
 }
 
+snapshot_fields!(CnROM {
+    cartridge,
+    bank_select
+});
+
 impl Mapper for CnROM {
     fn mirroring(&self) -> Mirroring {
         self.cartridge.header.mirroring
@@ -0,0 +1,197 @@
+use super::{Cartridge, Mapper, Mirroring};
+use crate::{apu::expansion::Sunsoft5bAudio, bus::CycleCount, savestate::snapshot_fields, util};
+
+/// The Sunsoft FME-7, and the 5B which is the same mapper with an added sound chip.
+/// https://www.nesdev.org/wiki/Sunsoft_FME-7
+#[allow(clippy::upper_case_acronyms)]
+pub struct FME7 {
+    cartridge: Cartridge,
+    program_ram: [u8; 0x2000],
+
+    command: u8,
+    character_banks: [u8; 8],
+    /// Banks for $8000, $A000 and $C000
+    program_banks: [u8; 3],
+    /// Bank register for $6000-$7FFF, which can map either program ROM or RAM
+    program_ram_bank: u8,
+    mirroring: Mirroring,
+
+    interrupt_enable: bool,
+    interrupt_counter_enable: bool,
+    interrupt_counter: u16,
+    interrupt_flag: bool,
+
+    audio: Sunsoft5bAudio,
+}
+
+impl FME7 {
+    pub fn new(cartridge: Cartridge) -> Self {
+        Self {
+            mirroring: cartridge.header.mirroring,
+            cartridge,
+            program_ram: [0; 0x2000],
+
+            command: 0,
+            character_banks: [0; 8],
+            program_banks: [0; 3],
+            program_ram_bank: 0,
+
+            interrupt_enable: false,
+            interrupt_counter_enable: false,
+            interrupt_counter: 0,
+            interrupt_flag: false,
+
+            audio: Sunsoft5bAudio::default(),
+        }
+    }
+
+    fn read_program_rom(&self, bank: usize, offset: u16) -> u8 {
+        let address = (bank * 0x2000) + offset as usize;
+        self.cartridge.program_rom[address % self.cartridge.program_rom.len()]
+    }
+
+    fn character_rom_address(&self, address: u16) -> usize {
+        let bank = self.character_banks[address as usize / 0x400] as usize;
+        ((bank * 0x400) + (address as usize % 0x400)) % self.cartridge.character_rom.len()
+    }
+
+    fn program_ram_selected(&self) -> bool {
+        util::nth_bit(self.program_ram_bank, 6)
+    }
+
+    fn program_ram_enabled(&self) -> bool {
+        util::nth_bit(self.program_ram_bank, 7)
+    }
+
+    /// https://www.nesdev.org/wiki/Sunsoft_FME-7#Parameter_Register_($A000-$BFFF)
+    fn write_parameter(&mut self, value: u8) {
+        match self.command {
+            0x0..=0x7 => self.character_banks[self.command as usize] = value,
+            0x8 => self.program_ram_bank = value,
+            0x9..=0xB => self.program_banks[self.command as usize - 0x9] = value & 0b0011_1111,
+            0xC => {
+                self.mirroring = match value & 0b0000_0011 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::OneScreenLower,
+                    _ => Mirroring::OneScreenUpper,
+                }
+            }
+            0xD => {
+                self.interrupt_enable = util::nth_bit(value, 0);
+                self.interrupt_counter_enable = util::nth_bit(value, 7);
+                self.interrupt_flag = false;
+            }
+            0xE => self.interrupt_counter = (self.interrupt_counter & 0xFF00) | value as u16,
+            0xF => self.interrupt_counter = (self.interrupt_counter & 0x00FF) | (value as u16) << 8,
+            _ => unreachable!(),
+        }
+    }
+}
+
+snapshot_fields!(FME7 {
+    cartridge,
+    program_ram,
+    command,
+    character_banks,
+    program_banks,
+    program_ram_bank,
+    mirroring,
+    interrupt_enable,
+    interrupt_counter_enable,
+    interrupt_counter,
+    interrupt_flag,
+    audio,
+});
+
+impl Mapper for FME7 {
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn read_cpu(&mut self, address: u16) -> u8 {
+        match address {
+            0x6000..=0x7FFF => {
+                if !self.program_ram_selected() {
+                    let bank = self.program_ram_bank & 0b0011_1111;
+                    self.read_program_rom(bank as usize, address - 0x6000)
+                } else if self.program_ram_enabled() {
+                    self.program_ram[(address - 0x6000) as usize]
+                } else {
+                    // Open bus
+                    0
+                }
+            }
+            0x8000..=0x9FFF => {
+                self.read_program_rom(self.program_banks[0] as usize, address - 0x8000)
+            }
+            0xA000..=0xBFFF => {
+                self.read_program_rom(self.program_banks[1] as usize, address - 0xA000)
+            }
+            0xC000..=0xDFFF => {
+                self.read_program_rom(self.program_banks[2] as usize, address - 0xC000)
+            }
+            0xE000..=0xFFFF => {
+                let last_bank = self.cartridge.program_rom.len() / 0x2000 - 1;
+                self.read_program_rom(last_bank, address - 0xE000)
+            }
+            _ => panic!("FME-7: Unhandled read at address: {address:#04X}"),
+        }
+    }
+
+    fn write_cpu(&mut self, address: u16, value: u8) {
+        match address {
+            0x6000..=0x7FFF => {
+                if self.program_ram_selected() && self.program_ram_enabled() {
+                    self.program_ram[(address - 0x6000) as usize] = value;
+                }
+            }
+            0x8000..=0x9FFF => self.command = value & 0b0000_1111,
+            0xA000..=0xBFFF => self.write_parameter(value),
+            0xC000..=0xDFFF => self.audio.write_address(value),
+            0xE000..=0xFFFF => self.audio.write_data(value),
+            _ => panic!("FME-7: Unhandled write at address: {address:#04X}"),
+        }
+    }
+
+    fn read_ppu(&mut self, address: u16) -> u8 {
+        self.cartridge.character_rom[self.character_rom_address(address)]
+    }
+
+    fn write_ppu(&mut self, address: u16, value: u8) {
+        let address = self.character_rom_address(address);
+        self.cartridge.character_rom[address] = value;
+    }
+
+    fn has_program_ram(&self) -> bool {
+        true
+    }
+
+    fn battery_ram(&mut self) -> Option<&mut [u8]> {
+        self.cartridge
+            .header
+            .battery
+            .then_some(&mut self.program_ram[..])
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.interrupt_flag
+    }
+
+    fn clock(&mut self, cycles: CycleCount) {
+        for _ in 0..cycles {
+            self.audio.clock();
+
+            if self.interrupt_counter_enable {
+                self.interrupt_counter = self.interrupt_counter.wrapping_sub(1);
+                if self.interrupt_counter == 0xFFFF && self.interrupt_enable {
+                    self.interrupt_flag = true;
+                }
+            }
+        }
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+}
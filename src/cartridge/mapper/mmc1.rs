@@ -1,5 +1,11 @@
-use super::{Cartridge, Mapper, Mirroring, PROGRAM_ROM_PAGE_SIZE, PROGRAM_ROM_START};
-use crate::util;
+use super::{
+    Cartridge, Mapper, Mirroring, PROGRAM_RAM_PAGE_SIZE, PROGRAM_ROM_PAGE_SIZE, PROGRAM_ROM_START,
+};
+use crate::{
+    bus::CycleCount,
+    savestate::{snapshot_bitfield, snapshot_fields},
+    util,
+};
 use tartan_bitfield::bitfield;
 
 enum ProgramRomBank {
@@ -34,10 +40,13 @@ bitfield! {
     }
 }
 
+snapshot_bitfield!(ControlRegister: u8);
+
 impl ControlRegister {
     fn mirroring(&self) -> Mirroring {
         match self.mirroring_mode() {
-            0 | 1 => Mirroring::OneScreen,
+            0 => Mirroring::OneScreenLower,
+            1 => Mirroring::OneScreenUpper,
             2 => Mirroring::Vertical,
             3 => Mirroring::Horizontal,
             _ => unreachable!(),
@@ -63,26 +72,42 @@ impl ControlRegister {
 }
 
 /// https://www.nesdev.org/wiki/MMC1
+///
+/// The boards with more memory than the registers can address reuse the upper bits of the character
+/// bank register: SUROM and SXROM select a 256 KB half of the program ROM with bit 4, SOROM and SXROM
+/// select a bank of program RAM with bit 3 and bits 2-3. Only the first bank register is used for this.
 #[allow(clippy::upper_case_acronyms)]
 pub struct MMC1 {
     cartridge: Cartridge,
+    program_ram: Vec<u8>,
 
     shift_count: u8,
     shift_register: u8,
+    /// Set by a write to the serial port and cleared on the next cycle, writes on consecutive cycles are ignored
+    written: bool,
 
     control: ControlRegister,
     character_bank_0: u8,
     character_bank_1: u8,
+    /// The highest bit disables program RAM
     program_bank: u8,
 }
 
 impl MMC1 {
+    const CHARACTER_BANK_SIZE: usize = 0x1000;
+    /// Program ROM beyond this is selected through the character bank register
+    const OUTER_PROGRAM_BANK_SIZE: usize = 256 * 1024;
+
     pub fn new(cartridge: Cartridge) -> Self {
+        let program_ram_size =
+            cartridge.header.program_ram_size + cartridge.header.program_nvram_size;
         Self {
             cartridge,
+            program_ram: vec![0; program_ram_size],
             shift_count: 0,
             shift_register: 0,
-            control: ControlRegister::default(),
+            written: false,
+            control: ControlRegister(0x0C),
             character_bank_0: 0,
             character_bank_1: 0,
             program_bank: 0,
@@ -99,56 +124,106 @@ impl MMC1 {
         self.shift_register & 0b0001_1111
     }
 
-    const fn bank(&self, index: u8) -> usize {
-        index as usize * PROGRAM_ROM_PAGE_SIZE
+    fn program_ram_enabled(&self) -> bool {
+        !util::nth_bit(self.program_bank, 4)
     }
 
-    fn ppu_bank(&self, address: u16) -> usize {
-        match address {
-            (0..=0x0FFF) => match self.control.character_rom_bank() {
-                CharacterRomBank::Consecutive => self.character_bank_0 & 0b1111_1110,
-                CharacterRomBank::Split => self.character_bank_0,
-            },
+    fn program_ram_address(&self, address: u16) -> usize {
+        let bank = match self.program_ram.len() / PROGRAM_RAM_PAGE_SIZE {
+            // SOROM
+            2 => (self.character_bank_0 >> 3) & 0b01,
+            // SXROM
+            4 => (self.character_bank_0 >> 2) & 0b11,
+            _ => 0,
+        };
+        (bank as usize * PROGRAM_RAM_PAGE_SIZE + (address - 0x6000) as usize)
+            % self.program_ram.len()
+    }
 
-            (0x1000..=0x1FFF) => match self.control.character_rom_bank() {
-                CharacterRomBank::Consecutive => self.character_bank_1 & 0b1111_1110,
-                CharacterRomBank::Split => self.character_bank_1,
-            },
+    fn program_rom_address(&self, address: u16) -> usize {
+        const LAST_BANK: u16 = PROGRAM_ROM_START + PROGRAM_ROM_PAGE_SIZE as u16;
 
-            _ => unreachable!(),
-        }
-        .into()
+        let outer_bank = if self.cartridge.program_rom.len() > Self::OUTER_PROGRAM_BANK_SIZE {
+            self.character_bank_0 & 0b1_0000
+        } else {
+            0
+        };
+        let bank = self.program_bank & 0b1111;
+
+        let bank = match (self.control.program_rom_bank(), address < LAST_BANK) {
+            (ProgramRomBank::Consecutive, true) => bank & 0b1110,
+            (ProgramRomBank::Consecutive, false) => bank | 1,
+            (ProgramRomBank::FixFirst, true) => 0,
+            (ProgramRomBank::FixFirst, false) => bank,
+            (ProgramRomBank::FixLast, true) => bank,
+            (ProgramRomBank::FixLast, false) => 0b1111,
+        } | outer_bank;
+
+        let offset = (address - PROGRAM_ROM_START) as usize % PROGRAM_ROM_PAGE_SIZE;
+        (bank as usize * PROGRAM_ROM_PAGE_SIZE + offset) % self.cartridge.program_rom.len()
+    }
+
+    /// Character RAM is banked the same way, it's just smaller
+    fn ppu_address(&self, address: u16) -> usize {
+        let upper_half = address as usize >= Self::CHARACTER_BANK_SIZE;
+        let bank = match self.control.character_rom_bank() {
+            CharacterRomBank::Consecutive => (self.character_bank_0 & 0b1_1110) | upper_half as u8,
+            CharacterRomBank::Split if upper_half => self.character_bank_1,
+            CharacterRomBank::Split => self.character_bank_0,
+        };
+
+        let offset = address as usize % Self::CHARACTER_BANK_SIZE;
+        (bank as usize * Self::CHARACTER_BANK_SIZE + offset) % self.cartridge.character_rom.len()
     }
 }
 
+snapshot_fields!(MMC1 {
+    cartridge,
+    program_ram,
+    shift_count,
+    shift_register,
+    control,
+    character_bank_0,
+    character_bank_1,
+    program_bank,
+});
+
 impl Mapper for MMC1 {
     fn mirroring(&self) -> Mirroring {
         self.control.mirroring()
     }
 
     fn read_cpu(&mut self, address: u16) -> u8 {
-        const LAST_BANK: u16 = PROGRAM_ROM_START + PROGRAM_ROM_PAGE_SIZE as u16;
-
-        if (PROGRAM_ROM_START..LAST_BANK).contains(&address) {
-            let bank = self.bank(match self.control.program_rom_bank() {
-                ProgramRomBank::Consecutive => self.program_bank & 0b1111_1110,
-                ProgramRomBank::FixFirst => self.program_bank,
-                ProgramRomBank::FixLast => 0,
-            });
-            self.cartridge.program_rom[bank + (address - PROGRAM_ROM_START) as usize]
-        } else {
-            let bank = self.bank(match self.control.program_rom_bank() {
-                ProgramRomBank::Consecutive => (self.program_bank & 0b1111_1110) | 1,
-                ProgramRomBank::FixFirst => self.program_bank,
-                ProgramRomBank::FixLast => self.cartridge.header.program_rom_pages as u8 - 1,
-            });
-            self.cartridge.program_rom[bank + (address - LAST_BANK) as usize]
+        match address {
+            0x6000..=0x7FFF => {
+                if self.program_ram_enabled() {
+                    self.program_ram[self.program_ram_address(address)]
+                } else {
+                    // Open bus
+                    0
+                }
+            }
+            _ => self.cartridge.program_rom[self.program_rom_address(address)],
         }
     }
 
     fn write_cpu(&mut self, address: u16, value: u8) {
+        if (0x6000..=0x7FFF).contains(&address) {
+            if self.program_ram_enabled() {
+                let address = self.program_ram_address(address);
+                self.program_ram[address] = value;
+            }
+            return;
+        }
+
+        // Read-modify-write instructions write twice in a row, only the first one counts
+        if std::mem::replace(&mut self.written, true) {
+            return;
+        }
+
         if util::nth_bit(value, 7) {
-            self.reset_shift()
+            self.reset_shift();
+            return;
         }
 
         self.shift_register |= (util::nth_bit(value, 0) as u8) << self.shift_count;
@@ -170,8 +245,7 @@ impl Mapper for MMC1 {
                 }
 
                 (0xE000..=0xFFFF) => {
-                    // TODO: Highest bit selects wether program RAM is enabled
-                    self.program_bank = self.read_shift() & 0b0000_1111;
+                    self.program_bank = self.read_shift();
                 }
 
                 _ => unreachable!(),
@@ -182,11 +256,89 @@ impl Mapper for MMC1 {
     }
 
     fn read_ppu(&mut self, address: u16) -> u8 {
-        self.cartridge.character_rom[(self.ppu_bank(address)) + address as usize]
+        self.cartridge.character_rom[self.ppu_address(address)]
     }
 
     fn write_ppu(&mut self, address: u16, value: u8) {
-        let bank = self.ppu_bank(address);
-        self.cartridge.character_rom[bank + address as usize] = value;
+        let address = self.ppu_address(address);
+        self.cartridge.character_rom[address] = value;
+    }
+
+    fn has_program_ram(&self) -> bool {
+        !self.program_ram.is_empty()
+    }
+
+    fn battery_ram(&mut self) -> Option<&mut [u8]> {
+        self.cartridge
+            .header
+            .battery
+            .then_some(&mut self.program_ram[..])
+    }
+
+    fn clock(&mut self, _cycles: CycleCount) {
+        self.written = false;
+    }
+
+    fn reset(&mut self) {
+        self.reset_shift();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A SUROM board with 512 KB of program ROM, where every bank starts with its own index
+    fn surom() -> MMC1 {
+        let mut data = vec![
+            b'N', b'E', b'S', 0x1A, 32, 0, 0x10, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        for bank in 0..32 {
+            let mut page = vec![0; PROGRAM_ROM_PAGE_SIZE];
+            page[0] = bank;
+            data.extend(page);
+        }
+        MMC1::new(Cartridge::from_bytes(&data).unwrap())
+    }
+
+    fn write_register(mmc1: &mut MMC1, address: u16, value: u8) {
+        for bit in 0..5 {
+            mmc1.write_cpu(address, value >> bit);
+            mmc1.clock(1);
+        }
+    }
+
+    #[test]
+    fn outer_program_bank() {
+        let mut mmc1 = surom();
+        write_register(&mut mmc1, 0xE000, 3);
+        assert_eq!(mmc1.read_cpu(0x8000), 3);
+        assert_eq!(mmc1.read_cpu(0xC000), 15);
+
+        // The last bank is fixed within the selected half
+        write_register(&mut mmc1, 0xA000, 0b1_0000);
+        assert_eq!(mmc1.read_cpu(0x8000), 19);
+        assert_eq!(mmc1.read_cpu(0xC000), 31);
+    }
+
+    #[test]
+    fn consecutive_writes() {
+        let mut mmc1 = surom();
+        write_register(&mut mmc1, 0xE000, 3);
+
+        // The second write of a read-modify-write instruction is ignored
+        mmc1.write_cpu(0x8000, 0x80);
+        mmc1.write_cpu(0x8000, 0x01);
+        mmc1.clock(1);
+        write_register(&mut mmc1, 0xE000, 5);
+        assert_eq!(mmc1.read_cpu(0x8000), 5);
+    }
+
+    #[test]
+    fn one_screen_mirroring() {
+        let mut mmc1 = surom();
+        assert!(matches!(mmc1.mirroring(), Mirroring::OneScreenLower));
+        write_register(&mut mmc1, 0x8000, 0x0D);
+        assert!(matches!(mmc1.mirroring(), Mirroring::OneScreenUpper));
     }
 }
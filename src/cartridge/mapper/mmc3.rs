@@ -1,5 +1,5 @@
 use super::{Cartridge, Mapper, Mirroring};
-use crate::util;
+use crate::{savestate::snapshot_fields, util};
 
 /// https://www.nesdev.org/wiki/MMC3
 pub struct MMC3 {
@@ -14,10 +14,10 @@ pub struct MMC3 {
     program_rom_bank_mode: bool,
     character_rom_bank_mode: bool,
 
-    // TODO: implement the interrupt mechanism, these are a placeholder
     interrupt_enable: bool,
     interrupt_flag: bool,
     interrupt_reset: bool,
+    interrupt_latch: u8,
     interrupt_counter: u8,
 }
 
@@ -38,6 +38,7 @@ impl MMC3 {
             interrupt_enable: false,
             interrupt_reset: false,
             interrupt_flag: false,
+            interrupt_latch: 0,
             interrupt_counter: 0,
         }
     }
@@ -51,6 +52,22 @@ impl MMC3 {
     }
 }
 
+snapshot_fields!(MMC3 {
+    cartridge,
+    program_ram,
+    program_ram_enabled,
+    program_ram_write_protected,
+    registers,
+    bank_index,
+    program_rom_bank_mode,
+    character_rom_bank_mode,
+    interrupt_enable,
+    interrupt_flag,
+    interrupt_reset,
+    interrupt_latch,
+    interrupt_counter,
+});
+
 impl Mapper for MMC3 {
     fn mirroring(&self) -> Mirroring {
         self.cartridge.header.mirroring
@@ -112,7 +129,7 @@ impl Mapper for MMC3 {
     }
 
     fn write_cpu(&mut self, address: u16, value: u8) {
-        match (address, address % 2 == 0) {
+        match (address, address.is_multiple_of(2)) {
             (0x6000..=0x7FFF, _) => {
                 if self.program_ram_enabled && !self.program_ram_write_protected {
                     let address = address - 0x6000;
@@ -150,7 +167,7 @@ impl Mapper for MMC3 {
             }
 
             (0xC000..=0xDFFF, true) => {
-                self.interrupt_counter = value;
+                self.interrupt_latch = value;
             }
 
             (0xC000..=0xDFFF, false) => {
@@ -203,4 +220,29 @@ impl Mapper for MMC3 {
     fn has_program_ram(&self) -> bool {
         true
     }
+
+    fn battery_ram(&mut self) -> Option<&mut [u8]> {
+        self.cartridge
+            .header
+            .battery
+            .then_some(&mut self.program_ram[..])
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.interrupt_flag
+    }
+
+    /// https://www.nesdev.org/wiki/MMC3#IRQ_Specifics
+    fn clock_scanline(&mut self) {
+        if self.interrupt_counter == 0 || self.interrupt_reset {
+            self.interrupt_counter = self.interrupt_latch;
+            self.interrupt_reset = false;
+        } else {
+            self.interrupt_counter -= 1;
+        }
+
+        if self.interrupt_counter == 0 && self.interrupt_enable {
+            self.interrupt_flag = true;
+        }
+    }
 }
@@ -0,0 +1,196 @@
+use super::{Cartridge, Mapper, Mirroring};
+use crate::{apu::expansion::Namco163Audio, bus::CycleCount, savestate::snapshot_fields, util};
+
+/// https://www.nesdev.org/wiki/Namco_163
+pub struct Namco163 {
+    cartridge: Cartridge,
+    program_ram: [u8; 0x2000],
+
+    program_banks: [u8; 3],
+    character_banks: [u8; 8],
+    nametable_banks: [u8; 4],
+
+    interrupt_counter: u16,
+    interrupt_enable: bool,
+    interrupt_flag: bool,
+
+    audio: Namco163Audio,
+    audio_enabled: bool,
+}
+
+impl Namco163 {
+    const INTERRUPT_COUNTER_MAX: u16 = 0x7FFF;
+
+    pub fn new(cartridge: Cartridge) -> Self {
+        Self {
+            cartridge,
+            program_ram: [0; 0x2000],
+
+            program_banks: [0; 3],
+            character_banks: [0; 8],
+            nametable_banks: [0; 4],
+
+            interrupt_counter: 0,
+            interrupt_enable: false,
+            interrupt_flag: false,
+
+            audio: Namco163Audio::default(),
+            audio_enabled: true,
+        }
+    }
+
+    fn read_program_rom(&self, bank: usize, offset: u16) -> u8 {
+        let address = (bank * 0x2000) + offset as usize;
+        self.cartridge.program_rom[address % self.cartridge.program_rom.len()]
+    }
+
+    fn character_rom_address(&self, address: u16) -> usize {
+        let bank = self.character_banks[address as usize / 0x400] as usize;
+        ((bank * 0x400) + (address as usize % 0x400)) % self.cartridge.character_rom.len()
+    }
+}
+
+snapshot_fields!(Namco163 {
+    cartridge,
+    program_ram,
+    program_banks,
+    character_banks,
+    nametable_banks,
+    interrupt_counter,
+    interrupt_enable,
+    interrupt_flag,
+    audio,
+    audio_enabled,
+});
+
+impl Mapper for Namco163 {
+    fn mirroring(&self) -> Mirroring {
+        // Banks $E0 and up select one of the console's nametables, the lowest bit picks which.
+        // TODO: nametables from character ROM, only layouts that match regular mirroring are supported.
+        if self.nametable_banks.iter().any(|&bank| bank < 0xE0) {
+            return self.cartridge.header.mirroring;
+        }
+
+        match self.nametable_banks.map(|bank| bank & 1) {
+            [0, 0, 1, 1] => Mirroring::Horizontal,
+            [0, 1, 0, 1] => Mirroring::Vertical,
+            [1, 1, 1, 1] => Mirroring::OneScreenUpper,
+            // TODO: other arrangements of the VRAM pages
+            _ => Mirroring::OneScreenLower,
+        }
+    }
+
+    fn read_cpu(&mut self, address: u16) -> u8 {
+        match address {
+            0x4800..=0x4FFF => self.audio.read_data(),
+            0x5000..=0x57FF => self.interrupt_counter as u8,
+            0x5800..=0x5FFF => {
+                ((self.interrupt_counter >> 8) as u8) | ((self.interrupt_enable as u8) << 7)
+            }
+            0x6000..=0x7FFF => self.program_ram[(address - 0x6000) as usize],
+            0x8000..=0x9FFF => {
+                self.read_program_rom(self.program_banks[0] as usize, address - 0x8000)
+            }
+            0xA000..=0xBFFF => {
+                self.read_program_rom(self.program_banks[1] as usize, address - 0xA000)
+            }
+            0xC000..=0xDFFF => {
+                self.read_program_rom(self.program_banks[2] as usize, address - 0xC000)
+            }
+            0xE000..=0xFFFF => {
+                let last_bank = self.cartridge.program_rom.len() / 0x2000 - 1;
+                self.read_program_rom(last_bank, address - 0xE000)
+            }
+            _ => {
+                tracing::trace!("Namco 163: unmapped read at ${:04X}", address);
+                // Open bus
+                0
+            }
+        }
+    }
+
+    fn write_cpu(&mut self, address: u16, value: u8) {
+        match address {
+            0x4800..=0x4FFF => self.audio.write_data(value),
+            0x5000..=0x57FF => {
+                self.interrupt_counter = (self.interrupt_counter & 0xFF00) | value as u16;
+                self.interrupt_flag = false;
+            }
+            0x5800..=0x5FFF => {
+                self.interrupt_counter =
+                    (self.interrupt_counter & 0x00FF) | (((value & 0b0111_1111) as u16) << 8);
+                self.interrupt_enable = util::nth_bit(value, 7);
+                self.interrupt_flag = false;
+            }
+            // TODO: write protection through $F800
+            0x6000..=0x7FFF => self.program_ram[(address - 0x6000) as usize] = value,
+
+            0x8000..=0xBFFF => self.character_banks[(address - 0x8000) as usize / 0x800] = value,
+            0xC000..=0xDFFF => self.nametable_banks[(address - 0xC000) as usize / 0x800] = value,
+
+            0xE000..=0xE7FF => {
+                self.program_banks[0] = value & 0b0011_1111;
+                self.audio_enabled = !util::nth_bit(value, 6);
+            }
+            0xE800..=0xEFFF => self.program_banks[1] = value & 0b0011_1111,
+            0xF000..=0xF7FF => self.program_banks[2] = value & 0b0011_1111,
+            0xF800..=0xFFFF => self.audio.write_address(value),
+
+            _ => tracing::trace!(
+                "Namco 163: ignoring write at ${:04X} = ${:02X}",
+                address,
+                value
+            ),
+        }
+    }
+
+    fn read_ppu(&mut self, address: u16) -> u8 {
+        self.cartridge.character_rom[self.character_rom_address(address)]
+    }
+
+    fn write_ppu(&mut self, address: u16, value: u8) {
+        let address = self.character_rom_address(address);
+        self.cartridge.character_rom[address] = value;
+    }
+
+    fn has_program_ram(&self) -> bool {
+        true
+    }
+
+    fn battery_ram(&mut self) -> Option<&mut [u8]> {
+        self.cartridge
+            .header
+            .battery
+            .then_some(&mut self.program_ram[..])
+    }
+
+    fn has_expansion_area(&self) -> bool {
+        true
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.interrupt_flag
+    }
+
+    fn clock(&mut self, cycles: CycleCount) {
+        for _ in 0..cycles {
+            self.audio.clock();
+
+            // The counter stops once it has fired
+            if self.interrupt_enable && self.interrupt_counter < Self::INTERRUPT_COUNTER_MAX {
+                self.interrupt_counter += 1;
+                if self.interrupt_counter == Self::INTERRUPT_COUNTER_MAX {
+                    self.interrupt_flag = true;
+                }
+            }
+        }
+    }
+
+    fn audio_output(&self) -> f32 {
+        if self.audio_enabled {
+            self.audio.output()
+        } else {
+            0.0
+        }
+    }
+}
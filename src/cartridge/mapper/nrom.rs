@@ -1,4 +1,5 @@
-use super::{Cartridge, Mapper, Mirroring, PROGRAM_ROM_PAGE_SIZE, PROGRAM_ROM_START};
+use super::{Cartridge, Mapper, Mirroring, PROGRAM_ROM_START};
+use crate::savestate::snapshot_fields;
 
 /// https://www.nesdev.org/wiki/NROM
 #[allow(clippy::upper_case_acronyms)]
@@ -12,17 +13,17 @@ impl NROM {
     }
 }
 
+snapshot_fields!(NROM { cartridge });
+
 impl Mapper for NROM {
     fn mirroring(&self) -> Mirroring {
         self.cartridge.header.mirroring
     }
 
-    fn read_cpu(&mut self, mut address: u16) -> u8 {
-        address -= PROGRAM_ROM_START;
-        if self.cartridge.header.program_rom_pages == 1 {
-            address %= PROGRAM_ROM_PAGE_SIZE as u16;
-        }
-        self.cartridge.program_rom[address as usize]
+    fn read_cpu(&mut self, address: u16) -> u8 {
+        // 16 KB of program ROM is mirrored across the whole range
+        let address = (address - PROGRAM_ROM_START) as usize % self.cartridge.program_rom.len();
+        self.cartridge.program_rom[address]
     }
 
     fn read_ppu(&mut self, address: u16) -> u8 {
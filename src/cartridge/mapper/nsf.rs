@@ -0,0 +1,305 @@
+use super::{Cartridge, Mapper, Mirroring};
+use crate::{
+    apu::expansion::{Namco163Audio, Sunsoft5bAudio, Vrc6Audio},
+    bus::CycleCount,
+    cartridge::nsf::Nsf,
+    region::Region,
+    savestate::snapshot_fields,
+};
+
+/// A pseudo-mapper to play NSF music files. It runs a small driver program that calls the INIT
+/// routine of the selected track, after which a timer IRQ calls the PLAY routine at the rate the file asks for.
+/// https://www.nesdev.org/wiki/NSF
+pub struct NsfPlayer {
+    nsf: Nsf,
+    character_ram: Vec<u8>,
+    program_ram: [u8; 0x2000],
+    driver: [u8; Self::DRIVER_LEN],
+
+    banks: [u8; 8],
+    /// Offset of the data within the first bank, the load address does not have to be aligned
+    padding: usize,
+
+    track: u8,
+    /// The timing the console runs at, which can differ from what the file asks for
+    region: Region,
+    play_enabled: bool,
+    play_pending: bool,
+    play_period: CycleCount,
+    play_timer: CycleCount,
+
+    vrc6: Option<Vrc6Audio>,
+    namco163: Option<Namco163Audio>,
+    sunsoft5b: Option<Sunsoft5bAudio>,
+}
+
+impl NsfPlayer {
+    const BANK_SIZE: usize = 0x1000;
+    const BANK_REGISTERS: u16 = 0x5FF8;
+
+    const DRIVER_ADDRESS: u16 = 0x4100;
+    const DRIVER_LEN: usize = 0x36;
+    const DRIVER_IRQ: u16 = Self::DRIVER_ADDRESS + 0x24;
+    const DRIVER_NMI: u16 = Self::DRIVER_ADDRESS + 0x35;
+
+    // Registers only the driver knows about, placed after it in the expansion area
+    const TRACK_REGISTER: u16 = 0x41F0;
+    const REGION_REGISTER: u16 = 0x41F1;
+    const PLAY_ENABLE_REGISTER: u16 = 0x41F2;
+    const PLAY_ACKNOWLEDGE_REGISTER: u16 = 0x41F3;
+
+    pub fn new(mut cartridge: Cartridge) -> Self {
+        let nsf = cartridge.nsf.take().unwrap();
+
+        let padding = if nsf.banks.is_some() {
+            nsf.load_address as usize % Self::BANK_SIZE
+        } else {
+            nsf.load_address.saturating_sub(0x8000) as usize
+        };
+
+        let mut player = Self {
+            driver: Self::driver(nsf.init_address, nsf.play_address),
+            character_ram: cartridge.character_rom,
+            program_ram: [0; 0x2000],
+            banks: [0; 8],
+            padding,
+            track: nsf.starting_song,
+            region: cartridge.header.region,
+            play_enabled: false,
+            play_pending: false,
+            play_period: 0,
+            play_timer: 0,
+            vrc6: None,
+            namco163: None,
+            sunsoft5b: None,
+            nsf,
+        };
+        player.set_region(player.region);
+        player.reset();
+        player
+    }
+
+    /// The program that drives playback, it is mapped at `DRIVER_ADDRESS`
+    #[rustfmt::skip]
+    const fn driver(init: u16, play: u16) -> [u8; Self::DRIVER_LEN] {
+        let [init_low, init_high] = init.to_le_bytes();
+        let [play_low, play_high] = play.to_le_bytes();
+
+        [
+            // Reset
+            0x78,                   // SEI
+            0xD8,                   // CLD
+            0xA2, 0xFF,             // LDX #$FF
+            0x9A,                   // TXS
+            0xA9, 0x00,             // LDA #$00
+            0x8D, 0x15, 0x40,       // STA $4015
+            0xA9, 0x0F,             // LDA #$0F
+            0x8D, 0x15, 0x40,       // STA $4015
+            0xA9, 0x40,             // LDA #$40
+            0x8D, 0x17, 0x40,       // STA $4017, disables the frame counter IRQ
+            0xAD, 0xF0, 0x41,       // LDA TRACK_REGISTER
+            0xAE, 0xF1, 0x41,       // LDX REGION_REGISTER
+            0x20, init_low, init_high, // JSR INIT
+            0x8D, 0xF2, 0x41,       // STA PLAY_ENABLE_REGISTER
+            0x58,                   // CLI
+            0x4C, 0x21, 0x41,       // JMP to itself, waiting for interrupts
+
+            // IRQ
+            0x48,                   // PHA
+            0x8A,                   // TXA
+            0x48,                   // PHA
+            0x98,                   // TYA
+            0x48,                   // PHA
+            0xAD, 0xF3, 0x41,       // LDA PLAY_ACKNOWLEDGE_REGISTER
+            0x20, play_low, play_high, // JSR PLAY
+            0x68,                   // PLA
+            0xA8,                   // TAY
+            0x68,                   // PLA
+            0xAA,                   // TAX
+            0x68,                   // PLA
+            0x40,                   // RTI
+
+            // NMI
+            0x40,                   // RTI
+        ]
+    }
+
+    fn read_program_rom(&self, address: u16) -> u8 {
+        let address = address as usize - 0x8000;
+        let bank = self.banks[address / Self::BANK_SIZE] as usize;
+        let offset = (bank * Self::BANK_SIZE) + (address % Self::BANK_SIZE);
+
+        offset
+            .checked_sub(self.padding)
+            .and_then(|offset| self.nsf.data.get(offset))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    fn read_vector(address: u16) -> u8 {
+        let vector = match address {
+            0xFFFA..=0xFFFB => Self::DRIVER_NMI,
+            0xFFFC..=0xFFFD => Self::DRIVER_ADDRESS,
+            _ => Self::DRIVER_IRQ,
+        };
+        vector.to_le_bytes()[address as usize % 2]
+    }
+}
+
+snapshot_fields!(NsfPlayer {
+    character_ram,
+    program_ram,
+    banks,
+    track,
+    play_enabled,
+    play_pending,
+    play_timer,
+    vrc6,
+    namco163,
+    sunsoft5b,
+});
+
+impl Mapper for NsfPlayer {
+    fn mirroring(&self) -> Mirroring {
+        Mirroring::Horizontal
+    }
+
+    fn read_cpu(&mut self, address: u16) -> u8 {
+        match address {
+            Self::TRACK_REGISTER => self.track,
+            // The Dendy has the CPU clock and APU of an NTSC console
+            Self::REGION_REGISTER => (self.region == Region::Pal) as u8,
+            Self::PLAY_ACKNOWLEDGE_REGISTER => {
+                self.play_pending = false;
+                0
+            }
+            0x4800..=0x4FFF if self.namco163.is_some() => {
+                self.namco163.as_mut().unwrap().read_data()
+            }
+            0x4100..=0x41FF => self
+                .driver
+                .get((address - Self::DRIVER_ADDRESS) as usize)
+                .copied()
+                .unwrap_or(0),
+            0x6000..=0x7FFF => self.program_ram[(address - 0x6000) as usize],
+            // The vectors of the tune itself are never used, we point them to the driver instead
+            0xFFFA..=0xFFFF => Self::read_vector(address),
+            0x8000..=0xFFFF => self.read_program_rom(address),
+            _ => {
+                tracing::trace!("NSF: unmapped read at ${:04X}", address);
+                // Open bus
+                0
+            }
+        }
+    }
+
+    fn write_cpu(&mut self, address: u16, value: u8) {
+        match address {
+            Self::PLAY_ENABLE_REGISTER => {
+                self.play_enabled = true;
+                self.play_timer = 0;
+            }
+            0x5FF8..=0x5FFF if self.nsf.banks.is_some() => {
+                self.banks[(address - Self::BANK_REGISTERS) as usize] = value;
+            }
+            0x6000..=0x7FFF => self.program_ram[(address - 0x6000) as usize] = value,
+
+            // Expansion audio uses the same registers as the cartridges it is found on
+            0x9000..=0x9003 | 0xA000..=0xA002 | 0xB000..=0xB002 if self.vrc6.is_some() => {
+                self.vrc6.as_mut().unwrap().write_register(address, value);
+            }
+            0x4800..=0x4FFF if self.namco163.is_some() => {
+                self.namco163.as_mut().unwrap().write_data(value);
+            }
+            0xF800..=0xFFFF if self.namco163.is_some() => {
+                self.namco163.as_mut().unwrap().write_address(value);
+            }
+            0xC000..=0xDFFF if self.sunsoft5b.is_some() => {
+                self.sunsoft5b.as_mut().unwrap().write_address(value);
+            }
+            0xE000..=0xFFFF if self.sunsoft5b.is_some() => {
+                self.sunsoft5b.as_mut().unwrap().write_data(value);
+            }
+
+            _ => tracing::trace!("NSF: ignoring write at ${:04X} = ${:02X}", address, value),
+        }
+    }
+
+    fn read_ppu(&mut self, address: u16) -> u8 {
+        self.character_ram[address as usize]
+    }
+
+    fn write_ppu(&mut self, address: u16, value: u8) {
+        self.character_ram[address as usize] = value;
+    }
+
+    fn has_expansion_area(&self) -> bool {
+        true
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.play_pending
+    }
+
+    fn clock(&mut self, cycles: CycleCount) {
+        for _ in 0..cycles {
+            if let Some(vrc6) = &mut self.vrc6 {
+                vrc6.clock();
+            }
+            if let Some(namco163) = &mut self.namco163 {
+                namco163.clock();
+            }
+            if let Some(sunsoft5b) = &mut self.sunsoft5b {
+                sunsoft5b.clock();
+            }
+        }
+
+        if self.play_enabled {
+            self.play_timer += cycles;
+            if self.play_timer >= self.play_period {
+                self.play_timer -= self.play_period;
+                self.play_pending = true;
+            }
+        }
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.vrc6.as_ref().map_or(0.0, Vrc6Audio::output)
+            + self.namco163.as_ref().map_or(0.0, Namco163Audio::output)
+            + self.sunsoft5b.as_ref().map_or(0.0, Sunsoft5bAudio::output)
+    }
+
+    /// PLAY is called at a fixed time interval, which takes more cycles on a faster CPU
+    fn set_region(&mut self, region: Region) {
+        self.region = region;
+        let clock_rate = region.cpu_clock_rate();
+        self.play_period = (self.nsf.play_speed as f64 * clock_rate / 1_000_000.0) as CycleCount;
+        tracing::info!(
+            "calling PLAY every {} cycles ({} microseconds)",
+            self.play_period,
+            self.nsf.play_speed
+        );
+    }
+
+    fn reset(&mut self) {
+        let chips = self.nsf.expansion_chips;
+        self.vrc6 = chips.vrc6().then(Vrc6Audio::default);
+        self.namco163 = chips.namco163().then(Namco163Audio::default);
+        self.sunsoft5b = chips.sunsoft5b().then(Sunsoft5bAudio::default);
+
+        self.program_ram = [0; 0x2000];
+        self.banks = self.nsf.banks.unwrap_or([0, 1, 2, 3, 4, 5, 6, 7]);
+        self.play_enabled = false;
+        self.play_pending = false;
+        self.play_timer = 0;
+    }
+
+    fn select_track(&mut self, track: u8) {
+        self.track = track.min(self.nsf.total_songs.saturating_sub(1));
+        tracing::info!(
+            "selected track {}: {}",
+            self.track + 1,
+            self.nsf.track_title(self.track)
+        );
+    }
+}
@@ -1,4 +1,5 @@
 use super::{Cartridge, Mapper, Mirroring, PROGRAM_ROM_PAGE_SIZE, PROGRAM_ROM_START};
+use crate::savestate::snapshot_fields;
 
 const LAST_BANK_START: u16 = PROGRAM_ROM_START + PROGRAM_ROM_PAGE_SIZE as u16;
 const FIRST_BANK_END: u16 = LAST_BANK_START - 1;
@@ -22,6 +23,11 @@ impl UxROM {
     }
 }
 
+snapshot_fields!(UxROM {
+    cartridge,
+    bank_select
+});
+
 impl Mapper for UxROM {
     fn mirroring(&self) -> Mirroring {
         self.cartridge.header.mirroring
@@ -39,7 +45,7 @@ impl Mapper for UxROM {
             // Last 16KB of program ROM
             (LAST_BANK_START..=0xFFFF) => {
                 address -= LAST_BANK_START;
-                let bank = self.bank(self.cartridge.header.program_rom_pages - 1);
+                let bank = self.bank(self.cartridge.header.program_rom_pages() - 1);
                 self.cartridge.program_rom[bank + address as usize]
             }
 
@@ -0,0 +1,231 @@
+use super::{Cartridge, Mapper, Mirroring};
+use crate::{apu::expansion::Vrc6Audio, bus::CycleCount, savestate::snapshot_fields, util};
+
+/// https://www.nesdev.org/wiki/VRC6
+#[allow(clippy::upper_case_acronyms)]
+pub struct VRC6 {
+    cartridge: Cartridge,
+    /// VRC6b (mapper 26) has the A0 and A1 address lines swapped compared to VRC6a (mapper 24)
+    swapped_lines: bool,
+
+    program_ram: [u8; 0x2000],
+    program_ram_enabled: bool,
+
+    program_bank_16k: u8,
+    program_bank_8k: u8,
+    character_banks: [u8; 8],
+    mirroring: Mirroring,
+
+    interrupt_latch: u8,
+    interrupt_counter: u8,
+    interrupt_prescaler: i16,
+    interrupt_enable: bool,
+    interrupt_enable_after_acknowledge: bool,
+    interrupt_cycle_mode: bool,
+    interrupt_flag: bool,
+
+    audio: Vrc6Audio,
+}
+
+impl VRC6 {
+    /// The IRQ counter is clocked once per scanline, which takes 341 PPU cycles or 113.667 CPU cycles
+    const PRESCALER_PERIOD: i16 = 341;
+
+    pub fn new(cartridge: Cartridge, swapped_lines: bool) -> Self {
+        Self {
+            mirroring: cartridge.header.mirroring,
+            cartridge,
+            swapped_lines,
+
+            program_ram: [0; 0x2000],
+            program_ram_enabled: false,
+
+            program_bank_16k: 0,
+            program_bank_8k: 0,
+            character_banks: [0; 8],
+
+            interrupt_latch: 0,
+            interrupt_counter: 0,
+            interrupt_prescaler: Self::PRESCALER_PERIOD,
+            interrupt_enable: false,
+            interrupt_enable_after_acknowledge: false,
+            interrupt_cycle_mode: false,
+            interrupt_flag: false,
+
+            audio: Vrc6Audio::default(),
+        }
+    }
+
+    fn read_program_rom(&self, bank: usize, bank_size: usize, offset: u16) -> u8 {
+        let address = (bank * bank_size) + offset as usize;
+        self.cartridge.program_rom[address % self.cartridge.program_rom.len()]
+    }
+
+    fn character_rom_address(&self, address: u16) -> usize {
+        let bank = self.character_banks[address as usize / 0x400] as usize;
+        ((bank * 0x400) + (address as usize % 0x400)) % self.cartridge.character_rom.len()
+    }
+
+    /// https://www.nesdev.org/wiki/VRC_IRQ
+    fn clock_interrupt_counter(&mut self) {
+        if self.interrupt_counter == 0xFF {
+            self.interrupt_counter = self.interrupt_latch;
+            self.interrupt_flag = true;
+        } else {
+            self.interrupt_counter += 1;
+        }
+    }
+}
+
+snapshot_fields!(VRC6 {
+    cartridge,
+    program_ram,
+    program_ram_enabled,
+    program_bank_16k,
+    program_bank_8k,
+    character_banks,
+    mirroring,
+    interrupt_latch,
+    interrupt_counter,
+    interrupt_prescaler,
+    interrupt_enable,
+    interrupt_enable_after_acknowledge,
+    interrupt_cycle_mode,
+    interrupt_flag,
+    audio,
+});
+
+impl Mapper for VRC6 {
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn read_cpu(&mut self, address: u16) -> u8 {
+        match address {
+            0x6000..=0x7FFF => {
+                if self.program_ram_enabled {
+                    self.program_ram[(address - 0x6000) as usize]
+                } else {
+                    // Open bus
+                    0
+                }
+            }
+            0x8000..=0xBFFF => {
+                self.read_program_rom(self.program_bank_16k as usize, 0x4000, address - 0x8000)
+            }
+            0xC000..=0xDFFF => {
+                self.read_program_rom(self.program_bank_8k as usize, 0x2000, address - 0xC000)
+            }
+            0xE000..=0xFFFF => {
+                let last_bank = self.cartridge.program_rom.len() / 0x2000 - 1;
+                self.read_program_rom(last_bank, 0x2000, address - 0xE000)
+            }
+            _ => panic!("VRC6: Unhandled read at address: {address:#04X}"),
+        }
+    }
+
+    fn write_cpu(&mut self, address: u16, value: u8) {
+        if (0x6000..=0x7FFF).contains(&address) {
+            if self.program_ram_enabled {
+                self.program_ram[(address - 0x6000) as usize] = value;
+            }
+            return;
+        }
+
+        // Only the lowest two address lines are connected within each register range
+        let address = if self.swapped_lines {
+            (address & 0xF000) | ((address & 0b01) << 1) | ((address & 0b10) >> 1)
+        } else {
+            address & 0xF003
+        };
+
+        match address {
+            0x8000..=0x8003 => self.program_bank_16k = value & 0b0000_1111,
+            0x9000..=0x9003 | 0xA000..=0xA002 | 0xB000..=0xB002 => {
+                self.audio.write_register(address, value);
+            }
+
+            0xB003 => {
+                // TODO: only the common banking mode where all character banks are 1KB is supported
+                self.mirroring = match (value >> 2) & 0b0000_0011 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::OneScreenLower,
+                    _ => Mirroring::OneScreenUpper,
+                };
+                self.program_ram_enabled = util::nth_bit(value, 7);
+            }
+
+            0xC000..=0xC003 => self.program_bank_8k = value & 0b0001_1111,
+            0xD000..=0xD003 => self.character_banks[(address & 0b0011) as usize] = value,
+            0xE000..=0xE003 => self.character_banks[4 + (address & 0b0011) as usize] = value,
+
+            0xF000 => self.interrupt_latch = value,
+            0xF001 => {
+                self.interrupt_enable_after_acknowledge = util::nth_bit(value, 0);
+                self.interrupt_enable = util::nth_bit(value, 1);
+                self.interrupt_cycle_mode = util::nth_bit(value, 2);
+                self.interrupt_flag = false;
+
+                if self.interrupt_enable {
+                    self.interrupt_counter = self.interrupt_latch;
+                    self.interrupt_prescaler = Self::PRESCALER_PERIOD;
+                }
+            }
+            0xF002 => {
+                self.interrupt_flag = false;
+                self.interrupt_enable = self.interrupt_enable_after_acknowledge;
+            }
+
+            _ => tracing::warn!("VRC6: ignoring write at ${:04X} = ${:02X}", address, value),
+        }
+    }
+
+    fn read_ppu(&mut self, address: u16) -> u8 {
+        self.cartridge.character_rom[self.character_rom_address(address)]
+    }
+
+    fn write_ppu(&mut self, address: u16, value: u8) {
+        let address = self.character_rom_address(address);
+        self.cartridge.character_rom[address] = value;
+    }
+
+    fn has_program_ram(&self) -> bool {
+        true
+    }
+
+    fn battery_ram(&mut self) -> Option<&mut [u8]> {
+        self.cartridge
+            .header
+            .battery
+            .then_some(&mut self.program_ram[..])
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.interrupt_flag
+    }
+
+    fn clock(&mut self, cycles: CycleCount) {
+        for _ in 0..cycles {
+            self.audio.clock();
+
+            if !self.interrupt_enable {
+                continue;
+            }
+
+            if self.interrupt_cycle_mode {
+                self.clock_interrupt_counter();
+            } else {
+                self.interrupt_prescaler -= 3;
+                if self.interrupt_prescaler <= 0 {
+                    self.interrupt_prescaler += Self::PRESCALER_PERIOD;
+                    self.clock_interrupt_counter();
+                }
+            }
+        }
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+}
@@ -1,21 +1,92 @@
 mod mapper;
+pub mod nsf;
+pub mod rom;
 
-pub use mapper::MapperInstance;
-use std::{fmt, path::PathBuf};
+use crate::{
+    region::Region,
+    savestate::{self, Snapshot, StateReader, StateWriter},
+};
+pub use mapper::{Mapper, MapperInstance};
+use nsf::Nsf;
+use std::{fmt, io};
 use tartan_bitfield::bitfield;
 
 // TODO: Nicer page abstraction
 pub const PROGRAM_ROM_START: u16 = 0x8000;
 pub const PROGRAM_ROM_PAGE_SIZE: usize = 16 * 1024;
 pub const CHARACTER_ROM_PAGE_SIZE: usize = 8 * 1024;
+pub const PROGRAM_RAM_PAGE_SIZE: usize = 8 * 1024;
 const TRAINER_SIZE: usize = 512;
 
+/// Why a ROM file could not be loaded
+#[derive(Debug)]
+pub enum CartridgeError {
+    Io(io::Error),
+    /// Neither an iNES nor a music file
+    InvalidSignature,
+    /// The file is shorter than its header says
+    Truncated {
+        expected: usize,
+        actual: usize,
+    },
+    UnsupportedMapper(u16),
+    /// The header declares no program ROM, so there is nothing to run
+    MissingProgramRom,
+    InvalidNsf(String),
+    Zip(zip::result::ZipError),
+    /// An archive without any file the emulator can load
+    NoRomInArchive,
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "failed to read file: {err}"),
+            Self::InvalidSignature => write!(f, "not a NES ROM or NSF file"),
+            Self::Truncated { expected, actual } => write!(
+                f,
+                "file is truncated, expected {expected} bytes but got {actual}"
+            ),
+            Self::UnsupportedMapper(mapper_id) => write!(f, "mapper {mapper_id} is not supported"),
+            Self::MissingProgramRom => write!(f, "the ROM does not contain any program code"),
+            Self::InvalidNsf(err) => write!(f, "invalid NSF file: {err}"),
+            Self::Zip(err) => write!(f, "failed to read zip archive: {err}"),
+            Self::NoRomInArchive => write!(f, "the archive does not contain a ROM"),
+        }
+    }
+}
+
+impl std::error::Error for CartridgeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            Self::Zip(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for CartridgeError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<zip::result::ZipError> for CartridgeError {
+    fn from(err: zip::result::ZipError) -> Self {
+        Self::Zip(err)
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 pub enum Mirroring {
     Horizontal,
     Vertical,
     FourScreen,
-    OneScreen,
+    /// Every nametable shows the first page of VRAM
+    OneScreenLower,
+    /// Every nametable shows the second page of VRAM
+    OneScreenUpper,
 }
 
 impl fmt::Display for Mirroring {
@@ -24,11 +95,30 @@ impl fmt::Display for Mirroring {
             Self::Horizontal => write!(f, "horizontal"),
             Self::Vertical => write!(f, "vertical"),
             Self::FourScreen => write!(f, "four-screen"),
-            Self::OneScreen => write!(f, "one-screen"),
+            Self::OneScreenLower => write!(f, "one-screen lower"),
+            Self::OneScreenUpper => write!(f, "one-screen upper"),
         }
     }
 }
 
+impl Snapshot for Mirroring {
+    fn save(&self, state: &mut StateWriter) {
+        (*self as u8).save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        *self = match state.read::<u8>()? {
+            0 => Self::Horizontal,
+            1 => Self::Vertical,
+            2 => Self::FourScreen,
+            3 => Self::OneScreenLower,
+            4 => Self::OneScreenUpper,
+            mirroring => return Err(format!("invalid mirroring {mirroring}")),
+        };
+        Ok(())
+    }
+}
+
 bitfield! {
     /// Flags 6 and 7 of the iNES header
     pub struct Flags(u16) {
@@ -40,34 +130,77 @@ bitfield! {
         [4..=7] pub mapper_id_low: u8,
 
         // https://www.nesdev.org/wiki/INES#Flags_7
-        [8..=11] pub mapper_id_high: u8,
-        [11..=13] pub ines: u8,
-        [14] pub playchoice_10,
-        [15] pub vs_unisystem,
+        [8..=9] pub console_type: u8,
+        [10..=11] pub identifier: u8,
+        [12..=15] pub mapper_id_high: u8,
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    INes,
+    /// https://www.nesdev.org/wiki/NES_2.0
+    Nes2,
+    /// Old dumps have garbage in bytes 7-15, such as the name of the tool that made them
+    ArchaicINes,
+}
+
 impl Flags {
-    pub fn mapper_id(&self) -> u8 {
-        self.mapper_id_low() | self.mapper_id_high()
+    pub fn format(&self) -> Format {
+        match self.identifier() {
+            0 => Format::INes,
+            2 => Format::Nes2,
+            _ => Format::ArchaicINes,
+        }
     }
+}
 
-    pub fn ines_version(&self) -> Option<u8> {
-        match self.ines() {
-            0 => Some(1),
-            2 => Some(2),
-            _ => None,
+/// https://www.nesdev.org/wiki/NES_2.0#Console_Type
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleType {
+    #[default]
+    Nes,
+    VsSystem,
+    Playchoice10,
+    /// A clone or other hardware, see https://www.nesdev.org/wiki/NES_2.0#Extended_Console_Type
+    Extended(u8),
+}
+
+impl fmt::Display for ConsoleType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Nes => write!(f, "NES/Famicom"),
+            Self::VsSystem => write!(f, "Vs. System"),
+            Self::Playchoice10 => write!(f, "PlayChoice-10"),
+            Self::Extended(console_type) => write!(f, "extended console type {console_type}"),
         }
     }
 }
 
 #[derive(Debug, Copy, Clone)]
 pub struct Header {
+    pub format: Format,
     pub mirroring: Mirroring,
-    pub program_rom_pages: usize,
-    character_rom_pages: usize,
+    pub program_rom_size: usize,
+    pub character_rom_size: usize,
     has_trainer: bool,
-    mapper_id: u8,
+    pub mapper_id: u16,
+    /// Tells apart boards that share a mapper number, only set by NES 2.0
+    pub submapper: u8,
+    pub region: Region,
+    /// Whether the program RAM is battery-backed
+    pub battery: bool,
+    /// Volatile program RAM
+    pub program_ram_size: usize,
+    /// Program RAM that is kept alive with a battery
+    pub program_nvram_size: usize,
+    /// Used when there is no character ROM
+    pub character_ram_size: usize,
+    pub character_nvram_size: usize,
+    pub console_type: ConsoleType,
+    /// The input device the game expects, 0 when unspecified and 1 for standard controllers.
+    /// https://www.nesdev.org/wiki/NES_2.0#Default_Expansion_Device
+    pub expansion_device: u8,
 }
 
 const HEADER_SIZE: usize = 16;
@@ -76,23 +209,17 @@ const HEADER_SIZE: usize = 16;
 impl Header {
     const SIGNATURE: [u8; 4] = [b'N', b'E', b'S', 0x1A];
 
-    fn new(data: [u8; 16]) -> Result<Self, String> {
+    fn new(data: [u8; 16]) -> Result<Self, CartridgeError> {
         if data[0..=3] != Self::SIGNATURE {
-            return Err("Invalid ROM file".to_string());
+            return Err(CartridgeError::InvalidSignature);
         }
 
-        let flags = Flags::from(u16::from_le_bytes([data[6], data[7]]));
-
-        if flags.ines_version() != Some(1) {
-            return Err(format!(
-                "Unsupported iNES version: {}",
-                if let Some(version) = flags.ines_version() {
-                    version.to_string()
-                } else {
-                    "unknown".to_string()
-                }
-            ));
-        }
+        let format = Flags::from(u16::from_le_bytes([data[6], data[7]])).format();
+        // Nothing after byte 6 can be trusted in archaic headers
+        let flags = match format {
+            Format::ArchaicINes => Flags::from(data[6] as u16),
+            _ => Flags::from(u16::from_le_bytes([data[6], data[7]])),
+        };
 
         let mirroring = if flags.four_screen() {
             Mirroring::FourScreen
@@ -103,13 +230,96 @@ impl Header {
             }
         };
 
-        Ok(Header {
-            has_trainer: flags.trainer(),
-            program_rom_pages: data[4] as usize,
-            character_rom_pages: data[5] as usize,
-            mapper_id: flags.mapper_id(),
+        let console_type = match flags.console_type() {
+            0 => ConsoleType::Nes,
+            1 => ConsoleType::VsSystem,
+            2 => ConsoleType::Playchoice10,
+            _ if format == Format::Nes2 => ConsoleType::Extended(data[13] & 0x0F),
+            _ => ConsoleType::Nes,
+        };
+
+        let mut header = Header {
+            format,
             mirroring,
-        })
+            program_rom_size: data[4] as usize * PROGRAM_ROM_PAGE_SIZE,
+            character_rom_size: data[5] as usize * CHARACTER_ROM_PAGE_SIZE,
+            has_trainer: flags.trainer(),
+            mapper_id: (flags.mapper_id_high() << 4 | flags.mapper_id_low()) as u16,
+            submapper: 0,
+            region: Region::Ntsc,
+            battery: flags.persistent_memory(),
+            program_ram_size: PROGRAM_RAM_PAGE_SIZE,
+            program_nvram_size: 0,
+            character_ram_size: CHARACTER_ROM_PAGE_SIZE,
+            character_nvram_size: 0,
+            console_type,
+            expansion_device: 0,
+        };
+
+        match format {
+            Format::Nes2 => header.read_nes2(&data),
+            Format::INes => {
+                // https://www.nesdev.org/wiki/INES#Flags_8, a size of 0 means 8 KB for compatibility
+                header.program_ram_size = data[8].max(1) as usize * PROGRAM_RAM_PAGE_SIZE;
+                // https://www.nesdev.org/wiki/INES#Flags_9
+                if data[9] & 1 != 0 {
+                    header.region = Region::Pal;
+                }
+            }
+            Format::ArchaicINes => {}
+        }
+        Ok(header)
+    }
+
+    /// https://www.nesdev.org/wiki/NES_2.0#File_Structure
+    fn read_nes2(&mut self, data: &[u8; 16]) {
+        self.mapper_id |= ((data[8] & 0x0F) as u16) << 8;
+        self.submapper = data[8] >> 4;
+
+        self.program_rom_size = Self::rom_size(data[4], data[9] & 0x0F, PROGRAM_ROM_PAGE_SIZE);
+        self.character_rom_size = Self::rom_size(data[5], data[9] >> 4, CHARACTER_ROM_PAGE_SIZE);
+
+        self.program_ram_size = Self::ram_size(data[10] & 0x0F);
+        self.program_nvram_size = Self::ram_size(data[10] >> 4);
+        self.character_ram_size = Self::ram_size(data[11] & 0x0F);
+        self.character_nvram_size = Self::ram_size(data[11] >> 4);
+
+        // https://www.nesdev.org/wiki/NES_2.0#CPU/PPU_Timing, multi-region games run as NTSC
+        self.region = match data[12] & 0b11 {
+            1 => Region::Pal,
+            3 => Region::Dendy,
+            _ => Region::Ntsc,
+        };
+        self.expansion_device = data[15] & 0b0011_1111;
+    }
+
+    /// https://www.nesdev.org/wiki/NES_2.0#PRG-ROM_Area
+    fn rom_size(low: u8, high: u8, page_size: usize) -> usize {
+        if high == 0x0F {
+            // Exponent-multiplier notation for sizes that aren't a multiple of the page size
+            let exponent = low >> 2;
+            let multiplier = (low & 0b11) as usize * 2 + 1;
+            // Sizes that don't fit can't be in the file anyway, which is caught as truncation
+            1usize
+                .checked_shl(exponent as u32)
+                .unwrap_or(usize::MAX)
+                .saturating_mul(multiplier)
+        } else {
+            ((high as usize) << 8 | low as usize) * page_size
+        }
+    }
+
+    /// https://www.nesdev.org/wiki/NES_2.0#PRG-(NV)RAM/EEPROM
+    const fn ram_size(shift: u8) -> usize {
+        if shift == 0 {
+            0
+        } else {
+            64 << shift
+        }
+    }
+
+    pub const fn program_rom_pages(&self) -> usize {
+        self.program_rom_size / PROGRAM_ROM_PAGE_SIZE
     }
 }
 
@@ -117,66 +327,263 @@ pub struct Cartridge {
     pub header: Header,
     pub program_rom: Vec<u8>,
     pub character_rom: Vec<u8>,
+    /// Set when playing back a music file instead of a game
+    pub nsf: Option<Nsf>,
 }
 
 impl Cartridge {
     pub const SPAN_NAME: &'static str = "cartridge";
 
-    pub fn from_bytes(data: &[u8]) -> Result<Cartridge, String> {
+    pub fn from_bytes(data: &[u8]) -> Result<Cartridge, CartridgeError> {
         let _span = tracing::span!(tracing::Level::INFO, Cartridge::SPAN_NAME).entered();
-        let header = Header::new(data[..HEADER_SIZE].try_into().unwrap())?;
-        let program_rom_size = header.program_rom_pages * PROGRAM_ROM_PAGE_SIZE;
-        let character_rom_size = header.character_rom_pages * CHARACTER_ROM_PAGE_SIZE;
+        if Nsf::is_nsf(data) {
+            let nsf = Nsf::from_bytes(data).map_err(CartridgeError::InvalidNsf)?;
+            return Ok(Self::from_nsf(nsf));
+        }
+
+        let header_data = data.get(..HEADER_SIZE).ok_or(CartridgeError::Truncated {
+            expected: HEADER_SIZE,
+            actual: data.len(),
+        })?;
+        let mut header = Header::new(header_data.try_into().unwrap())?;
+        if header.program_rom_size == 0 {
+            return Err(CartridgeError::MissingProgramRom);
+        }
+        let program_rom_size = header.program_rom_size;
+        let character_rom_size = header.character_rom_size;
 
         let program_rom_start = HEADER_SIZE + if header.has_trainer { TRAINER_SIZE } else { 0 };
-        let character_rom_start = program_rom_start + program_rom_size;
+        let character_rom_start = program_rom_start.saturating_add(program_rom_size);
 
         tracing::debug!(header.has_trainer);
+        tracing::info!("{:?} header", header.format);
+        tracing::info!("{} bytes of program ROM", program_rom_size);
+        tracing::info!("{} bytes of character ROM", character_rom_size);
         tracing::info!(
-            "{} program ROM page(s), {} bytes",
-            header.program_rom_pages,
-            program_rom_size
+            "{} bytes of program RAM, {} bytes of program NVRAM",
+            header.program_ram_size,
+            header.program_nvram_size
         );
+        tracing::info!("{} mirroring", header.mirroring);
+        tracing::info!("{} region", header.region);
+        tracing::info!("battery-backed RAM: {}", header.battery);
         tracing::info!(
-            "{} character ROM page(s), {} bytes",
-            header.character_rom_pages,
-            character_rom_size
+            "mapper {}, submapper {}",
+            header.mapper_id,
+            header.submapper
         );
-        tracing::info!("{} mirroring", header.mirroring);
-        tracing::info!("mapper {}\n", header.mapper_id);
+        tracing::info!("{} console", header.console_type);
+        tracing::debug!(header.expansion_device);
+        if header.console_type != ConsoleType::Nes {
+            tracing::warn!("{} games are not supported", header.console_type);
+        }
+
+        let expected = character_rom_start.saturating_add(character_rom_size);
+        if data.len() < expected {
+            return Err(CartridgeError::Truncated {
+                expected,
+                actual: data.len(),
+            });
+        }
 
-        let program_rom = data[program_rom_start..(program_rom_start + program_rom_size)].to_vec();
+        let mut program_rom =
+            data[program_rom_start..(program_rom_start + program_rom_size)].to_vec();
         let mut character_rom =
             data[character_rom_start..(character_rom_start + character_rom_size)].to_vec();
 
+        // NES 2.0 allows smaller sizes than a page, which only repeat across the address space
+        mirror_to_page(&mut program_rom, PROGRAM_ROM_PAGE_SIZE);
+        header.program_rom_size = program_rom.len();
+
         // Character RAM is used when there is none provided by the cartridge
         // TODO: handle this from the mapper
-        if header.character_rom_pages == 0 {
-            character_rom.resize(CHARACTER_ROM_PAGE_SIZE, 0);
+        if character_rom_size == 0 {
+            let character_ram_size = header.character_ram_size + header.character_nvram_size;
+            character_rom.resize(character_ram_size.max(CHARACTER_ROM_PAGE_SIZE), 0);
+        } else {
+            mirror_to_page(&mut character_rom, CHARACTER_ROM_PAGE_SIZE);
+            header.character_rom_size = character_rom.len();
         }
 
         Ok(Cartridge {
             program_rom,
             character_rom,
             header,
+            nsf: None,
         })
     }
-}
 
-impl From<PathBuf> for Cartridge {
-    fn from(path: PathBuf) -> Self {
-        let data = std::fs::read(&path).unwrap_or_else(|err| {
-            tracing::error!("failed to read file \"{}\": \"{}\"", path.display(), err);
-            std::process::exit(1);
-        });
+    fn from_nsf(nsf: Nsf) -> Cartridge {
+        tracing::info!(
+            "NSF: \"{}\" by {}, {}",
+            nsf.title,
+            nsf.artist,
+            nsf.copyright
+        );
+        tracing::info!(
+            "{} track(s), load ${:04X}, init ${:04X}, play ${:04X}",
+            nsf.total_songs,
+            nsf.load_address,
+            nsf.init_address,
+            nsf.play_address
+        );
+        tracing::debug!(nsf.banks = ?nsf.banks, nsf.expansion_chips = ?nsf.expansion_chips);
 
-        Cartridge::from_bytes(&data).unwrap_or_else(|err| {
-            tracing::error!(
-                "failed to load cartridge from \"{}\": \"{}\"",
-                path.display(),
-                err
+        if nsf.expansion_chips.unsupported() {
+            tracing::warn!(
+                "unsupported expansion audio chips: {:?}",
+                nsf.expansion_chips
             );
-            std::process::exit(1);
-        })
+        }
+
+        Cartridge {
+            header: Header {
+                format: Format::INes,
+                mirroring: Mirroring::Horizontal,
+                program_rom_size: 0,
+                character_rom_size: 0,
+                has_trainer: false,
+                mapper_id: 0,
+                submapper: 0,
+                region: if nsf.pal { Region::Pal } else { Region::Ntsc },
+                battery: false,
+                program_ram_size: PROGRAM_RAM_PAGE_SIZE,
+                program_nvram_size: 0,
+                character_ram_size: CHARACTER_ROM_PAGE_SIZE,
+                character_nvram_size: 0,
+                console_type: ConsoleType::Nes,
+                expansion_device: 0,
+            },
+            program_rom: Vec::new(),
+            // The player does not draw anything, but the PPU still needs something to read from
+            character_rom: vec![0; CHARACTER_ROM_PAGE_SIZE],
+            nsf: Some(nsf),
+        }
+    }
+
+    /// Identifies the ROM, so save states can't be loaded into a different game
+    pub fn checksum(&self) -> u64 {
+        let nsf_data = self.nsf.iter().flat_map(|nsf| &nsf.data);
+        savestate::checksum(
+            self.program_rom
+                .iter()
+                .chain(&self.character_rom)
+                .chain(nsf_data),
+        )
+    }
+}
+
+/// Repeat memory that is smaller than a page until it fills one, mappers expect whole pages
+fn mirror_to_page(memory: &mut Vec<u8>, page_size: usize) {
+    if memory.is_empty() || memory.len() >= page_size {
+        return;
+    }
+    while memory.len() < page_size {
+        memory.extend_from_within(..);
+    }
+    memory.truncate(page_size);
+}
+
+/// Only the parts that can change while running are saved, which is the mirroring for mappers
+/// that don't keep track of it separately and the character RAM.
+impl Snapshot for Cartridge {
+    fn save(&self, state: &mut StateWriter) {
+        self.header.mirroring.save(state);
+        if self.header.character_rom_size == 0 {
+            self.character_rom.save(state);
+        }
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.header.mirroring.load(state)?;
+        if self.header.character_rom_size == 0 {
+            self.character_rom.load(state)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nes2_header() {
+        let mut data = [0; HEADER_SIZE];
+        data[0..4].copy_from_slice(&Header::SIGNATURE);
+        data[4] = 0x02;
+        data[6] = 0b0001_0011;
+        data[7] = 0b0000_1000;
+        // Mapper 257, submapper 3, character ROM in exponent-multiplier notation
+        data[8] = 0x31;
+        data[9] = 0xF0;
+        data[5] = 0b0000_1101;
+        data[10] = 0x70;
+        data[12] = 3;
+
+        let header = Header::new(data).unwrap();
+        assert_eq!(header.format, Format::Nes2);
+        assert_eq!(header.mapper_id, 257);
+        assert_eq!(header.submapper, 3);
+        assert_eq!(header.program_rom_size, 2 * PROGRAM_ROM_PAGE_SIZE);
+        assert_eq!(header.character_rom_size, 8 * 3);
+        assert_eq!(header.program_ram_size, 0);
+        assert_eq!(header.program_nvram_size, 8 * 1024);
+        assert_eq!(header.region, Region::Dendy);
+        assert!(header.battery);
+
+        // Garbage after byte 6 is ignored in archaic headers
+        data[7] = b'D';
+        data[8] = b'i';
+        let header = Header::new(data).unwrap();
+        assert_eq!(header.format, Format::ArchaicINes);
+        assert_eq!(header.mapper_id, 1);
+        assert_eq!(header.region, Region::Ntsc);
+        assert_eq!(header.program_ram_size, PROGRAM_RAM_PAGE_SIZE);
+    }
+
+    #[test]
+    fn loading_errors() {
+        let mut data = vec![0; HEADER_SIZE + PROGRAM_ROM_PAGE_SIZE];
+        data[0..4].copy_from_slice(&Header::SIGNATURE);
+        data[4] = 1;
+        data[5] = 1;
+
+        assert!(matches!(
+            Cartridge::from_bytes(&data[..8]),
+            Err(CartridgeError::Truncated { actual: 8, .. })
+        ));
+        assert!(matches!(
+            Cartridge::from_bytes(&data),
+            Err(CartridgeError::Truncated { .. })
+        ));
+
+        data[4] = 0;
+        assert!(matches!(
+            Cartridge::from_bytes(&data[..HEADER_SIZE]),
+            Err(CartridgeError::MissingProgramRom)
+        ));
+
+        // An 8 KB program ROM in exponent-multiplier notation, which fills a whole page
+        data[4] = 13 << 2;
+        data[5] = 0;
+        data[7] = 0b0000_1000;
+        data[9] = 0x0F;
+        data[HEADER_SIZE] = 0x42;
+        let cartridge = Cartridge::from_bytes(&data[..HEADER_SIZE + 8 * 1024]).unwrap();
+        assert_eq!(cartridge.program_rom.len(), PROGRAM_ROM_PAGE_SIZE);
+        assert_eq!(cartridge.header.program_rom_pages(), 1);
+        assert_eq!(cartridge.program_rom[8 * 1024], 0x42);
+        let mut mapper = Box::<dyn mapper::Mapper>::try_from(cartridge).unwrap();
+        assert_eq!(mapper.read_cpu(0xE000), 0x42);
+
+        data[4] = 1;
+        data[9] = 0;
+        data[7] = 0xF0;
+        let cartridge = Cartridge::from_bytes(&data).unwrap();
+        assert!(matches!(
+            Box::<dyn mapper::Mapper>::try_from(cartridge),
+            Err(CartridgeError::UnsupportedMapper(240))
+        ));
     }
 }
@@ -0,0 +1,254 @@
+//! Parsing of NSF and NSFe music files.
+
+use crate::util;
+use tartan_bitfield::bitfield;
+
+bitfield! {
+    /// https://www.nesdev.org/wiki/NSF#Sound_Chip_Support
+    pub struct ExpansionChips(u8) {
+        [0] pub vrc6,
+        [1] pub vrc7,
+        [2] pub fds,
+        [3] pub mmc5,
+        [4] pub namco163,
+        [5] pub sunsoft5b,
+    }
+}
+
+impl ExpansionChips {
+    /// The chips we do not emulate
+    pub fn unsupported(&self) -> bool {
+        self.vrc7() || self.fds() || self.mmc5()
+    }
+}
+
+/// https://www.nesdev.org/wiki/NSF
+#[derive(Debug, Clone)]
+pub struct Nsf {
+    pub total_songs: u8,
+    /// Zero-based index of the song to play first
+    pub starting_song: u8,
+
+    pub load_address: u16,
+    pub init_address: u16,
+    pub play_address: u16,
+    /// The interval to call the play routine at, in microseconds
+    pub play_speed: u16,
+
+    /// The initial banks for $8000-$FFFF, if the tune uses bankswitching
+    pub banks: Option<[u8; 8]>,
+    pub pal: bool,
+    pub expansion_chips: ExpansionChips,
+
+    pub title: String,
+    pub artist: String,
+    pub copyright: String,
+    pub track_titles: Vec<String>,
+
+    pub data: Vec<u8>,
+}
+
+impl Nsf {
+    const SIGNATURE: [u8; 5] = [b'N', b'E', b'S', b'M', 0x1A];
+    const SIGNATURE_NSFE: [u8; 4] = [b'N', b'S', b'F', b'E'];
+    const HEADER_SIZE: usize = 0x80;
+
+    /// The play rate most tunes use, which matches the NTSC frame rate
+    const DEFAULT_PLAY_SPEED: u16 = 16639;
+    /// Likewise for the PAL frame rate
+    const DEFAULT_PLAY_SPEED_PAL: u16 = 19997;
+
+    pub fn is_nsf(data: &[u8]) -> bool {
+        data.starts_with(&Self::SIGNATURE) || data.starts_with(&Self::SIGNATURE_NSFE)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, String> {
+        if data.starts_with(&Self::SIGNATURE) {
+            Self::parse_nsf(data)
+        } else if data.starts_with(&Self::SIGNATURE_NSFE) {
+            Self::parse_nsfe(data)
+        } else {
+            Err("Invalid NSF file".to_string())
+        }
+    }
+
+    /// The title of a track, falling back to its number if the file does not name it
+    pub fn track_title(&self, track: u8) -> String {
+        self.track_titles
+            .get(track as usize)
+            .filter(|title| !title.is_empty())
+            .cloned()
+            .unwrap_or_else(|| format!("Track {}", track + 1))
+    }
+
+    fn parse_nsf(data: &[u8]) -> Result<Self, String> {
+        if data.len() < Self::HEADER_SIZE {
+            return Err("NSF file is too short".to_string());
+        }
+
+        let word = |offset: usize| u16::from_le_bytes([data[offset], data[offset + 1]]);
+        let banks: [u8; 8] = data[0x70..0x78].try_into().unwrap();
+        // Dual region tunes are played as NTSC
+        let pal = data[0x7A] & 0b11 == 1;
+        let (play_speed, default_play_speed) = if pal {
+            (word(0x78), Self::DEFAULT_PLAY_SPEED_PAL)
+        } else {
+            (word(0x6E), Self::DEFAULT_PLAY_SPEED)
+        };
+
+        Ok(Self {
+            total_songs: data[0x06],
+            starting_song: data[0x07].saturating_sub(1),
+            load_address: word(0x08),
+            init_address: word(0x0A),
+            play_address: word(0x0C),
+            play_speed: if play_speed == 0 {
+                default_play_speed
+            } else {
+                play_speed
+            },
+            banks: banks.iter().any(|&bank| bank != 0).then_some(banks),
+            pal,
+            expansion_chips: data[0x7B].into(),
+            title: Self::parse_string(&data[0x0E..0x2E]),
+            artist: Self::parse_string(&data[0x2E..0x4E]),
+            copyright: Self::parse_string(&data[0x4E..0x6E]),
+            track_titles: Vec::new(),
+            data: data[Self::HEADER_SIZE..].to_vec(),
+        })
+    }
+
+    /// https://www.nesdev.org/wiki/NSFe
+    fn parse_nsfe(data: &[u8]) -> Result<Self, String> {
+        let mut nsf = Self {
+            total_songs: 1,
+            starting_song: 0,
+            load_address: 0,
+            init_address: 0,
+            play_address: 0,
+            play_speed: Self::DEFAULT_PLAY_SPEED,
+            banks: None,
+            pal: false,
+            expansion_chips: ExpansionChips::default(),
+            title: String::new(),
+            artist: String::new(),
+            copyright: String::new(),
+            track_titles: Vec::new(),
+            data: Vec::new(),
+        };
+
+        let mut has_info = false;
+        let mut offset = Self::SIGNATURE_NSFE.len();
+        while offset + 8 <= data.len() {
+            let len = u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap()) as usize;
+            let id = &data[offset + 4..offset + 8];
+            let chunk = data.get(offset + 8..offset + 8 + len).ok_or_else(|| {
+                format!("NSFe chunk {} is truncated", String::from_utf8_lossy(id))
+            })?;
+            offset += 8 + len;
+
+            match id {
+                b"INFO" => {
+                    if chunk.len() < 8 {
+                        return Err("NSFe INFO chunk is too short".to_string());
+                    }
+                    let word =
+                        |offset: usize| u16::from_le_bytes([chunk[offset], chunk[offset + 1]]);
+                    nsf.load_address = word(0);
+                    nsf.init_address = word(2);
+                    nsf.play_address = word(4);
+                    nsf.pal = chunk[6] & 0b11 == 1;
+                    if nsf.pal {
+                        nsf.play_speed = Self::DEFAULT_PLAY_SPEED_PAL;
+                    }
+                    nsf.expansion_chips = chunk[7].into();
+                    nsf.total_songs = chunk.get(8).copied().unwrap_or(1);
+                    nsf.starting_song = chunk.get(9).copied().unwrap_or(0);
+                    has_info = true;
+                }
+                b"DATA" => nsf.data = chunk.to_vec(),
+                b"BANK" => {
+                    let mut banks = [0; 8];
+                    for (bank, value) in banks.iter_mut().zip(chunk) {
+                        *bank = *value;
+                    }
+                    nsf.banks = Some(banks);
+                }
+                b"RATE" => {
+                    // The PAL rate follows the NTSC one
+                    let offset = if nsf.pal { 2 } else { 0 };
+                    if let Some(rate) = chunk.get(offset..offset + 2) {
+                        nsf.play_speed = u16::from_le_bytes([rate[0], rate[1]]);
+                    }
+                }
+                b"auth" => {
+                    let mut strings = chunk.split(|&byte| byte == 0).map(Self::parse_string);
+                    nsf.title = strings.next().unwrap_or_default();
+                    nsf.artist = strings.next().unwrap_or_default();
+                    nsf.copyright = strings.next().unwrap_or_default();
+                }
+                b"tlbl" => {
+                    nsf.track_titles = chunk
+                        .split(|&byte| byte == 0)
+                        .map(Self::parse_string)
+                        .take(nsf.total_songs as usize)
+                        .collect();
+                }
+                b"NEND" => break,
+                _ => {
+                    // Chunks starting with an uppercase letter are required to play the file correctly
+                    if util::nth_bit(id[0], 5) {
+                        tracing::debug!("skipping NSFe chunk {}", String::from_utf8_lossy(id));
+                    } else {
+                        return Err(format!(
+                            "unsupported NSFe chunk {}",
+                            String::from_utf8_lossy(id)
+                        ));
+                    }
+                }
+            }
+        }
+
+        if !has_info || nsf.data.is_empty() {
+            return Err("NSFe file is missing an INFO or DATA chunk".to_string());
+        }
+        Ok(nsf)
+    }
+
+    fn parse_string(data: &[u8]) -> String {
+        let end = data
+            .iter()
+            .position(|&byte| byte == 0)
+            .unwrap_or(data.len());
+        String::from_utf8_lossy(&data[..end]).trim().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_nsfe() {
+        let mut data = b"NSFE".to_vec();
+        let mut chunk = |id: &[u8], contents: &[u8]| {
+            data.extend((contents.len() as u32).to_le_bytes());
+            data.extend(id);
+            data.extend(contents);
+        };
+
+        chunk(b"INFO", &[0x00, 0x80, 0x03, 0x80, 0x06, 0x80, 0, 0, 2, 1]);
+        chunk(b"DATA", &[0x60; 16]);
+        chunk(b"tlbl", b"Intro\0Boss\0");
+        chunk(b"NEND", &[]);
+
+        let nsf = Nsf::from_bytes(&data).unwrap();
+        assert_eq!(nsf.load_address, 0x8000);
+        assert_eq!(nsf.play_address, 0x8006);
+        assert_eq!(nsf.total_songs, 2);
+        assert_eq!(nsf.starting_song, 1);
+        assert_eq!(nsf.track_title(1), "Boss");
+        assert_eq!(nsf.data.len(), 16);
+        assert!(nsf.banks.is_none());
+    }
+}
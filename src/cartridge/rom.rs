@@ -0,0 +1,150 @@
+//! ROM images read into memory, either straight from disk or from inside a compressed file
+
+use super::CartridgeError;
+use flate2::read::GzDecoder;
+use std::{
+    ffi::OsStr,
+    fs::File,
+    io::{self, Read, Seek},
+    path::{Path, PathBuf},
+};
+use zip::ZipArchive;
+
+/// What the emulator can load, looked for inside archives
+pub const ROM_EXTENSIONS: [&str; 3] = ["nes", "nsf", "nsfe"];
+pub const ARCHIVE_EXTENSIONS: [&str; 2] = ["zip", "gz"];
+
+/// Sent to the emulator thread, in order so a ROM can't be unloaded right after it was loaded
+pub enum RomRequest {
+    /// Replace the current cartridge, if any
+    Load(Rom),
+    Unload,
+}
+
+pub struct Rom {
+    /// Shown to the user, the file name or the name of the entry in an archive
+    pub name: String,
+    pub data: Vec<u8>,
+    /// Save states and battery-backed RAM are stored next to it. For archives this is where the
+    /// entry would be if it was extracted next to the archive, so every entry has its own saves.
+    pub path: Option<PathBuf>,
+}
+
+impl Rom {
+    /// Read a ROM file, decompressing it if needed. `entry` picks the file in a zip archive,
+    /// the first ROM in it is used otherwise.
+    pub fn read(path: &Path, entry: Option<&str>) -> Result<Self, CartridgeError> {
+        let file = File::open(path)?;
+        let (name, data) = match extension(path).as_str() {
+            "zip" => read_zip(file, entry)?,
+            // "game.nes.gz" holds "game.nes"
+            "gz" => (file_name(path.file_stem()), read_all(GzDecoder::new(file))?),
+            _ => (file_name(path.file_name()), read_all(file)?),
+        };
+
+        // Entries can be in folders, the saves still go next to the archive
+        let entry_path = path.with_file_name(Path::new(&name).file_name().unwrap_or_default());
+        Ok(Self {
+            name,
+            data,
+            path: Some(entry_path),
+        })
+    }
+
+    /// The ROMs in a zip archive, in the order they are stored. Empty for other files.
+    pub fn zip_entries(path: &Path) -> Result<Vec<String>, CartridgeError> {
+        if extension(path) != "zip" {
+            return Ok(Vec::new());
+        }
+        Ok(rom_entries(&mut ZipArchive::new(File::open(path)?)?))
+    }
+}
+
+/// Whether the file can be opened, as a ROM or as an archive of ROMs
+pub fn is_supported(path: &Path) -> bool {
+    let extension = extension(path);
+    ROM_EXTENSIONS
+        .iter()
+        .chain(&ARCHIVE_EXTENSIONS)
+        .any(|supported| extension == *supported)
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_lowercase()
+}
+
+fn file_name(name: Option<&OsStr>) -> String {
+    name.unwrap_or_default().to_string_lossy().to_string()
+}
+
+fn read_all(mut reader: impl Read) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+fn rom_entries<R: Read + Seek>(archive: &mut ZipArchive<R>) -> Vec<String> {
+    (0..archive.len())
+        .filter_map(|index| {
+            let file = archive.by_index(index).ok()?;
+            file.is_file().then(|| file.name().to_string())
+        })
+        .filter(|name| {
+            let extension = extension(Path::new(name));
+            ROM_EXTENSIONS.iter().any(|rom| extension == *rom)
+        })
+        .collect()
+}
+
+fn read_zip<R: Read + Seek>(
+    reader: R,
+    entry: Option<&str>,
+) -> Result<(String, Vec<u8>), CartridgeError> {
+    let mut archive = ZipArchive::new(reader)?;
+    let name = match entry {
+        Some(entry) => entry.to_string(),
+        None => rom_entries(&mut archive)
+            .into_iter()
+            .next()
+            .ok_or(CartridgeError::NoRomInArchive)?,
+    };
+
+    let data = read_all(archive.by_name(&name)?)?;
+    Ok((name, data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+    use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+    #[test]
+    fn zip_entries() {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+        for (name, data) in [
+            ("readme.txt", "hi"),
+            ("disk.fds", "FDS"),
+            ("b.nes", "NES2"),
+            ("a.NES", "NES1"),
+        ] {
+            writer.start_file(name, options).unwrap();
+            writer.write_all(data.as_bytes()).unwrap();
+        }
+        let mut zip = writer.finish().unwrap();
+
+        // In the order they are stored, whatever the case of the extension.
+        // Disk images are skipped since nothing can load them.
+        let mut archive = ZipArchive::new(&mut zip).unwrap();
+        assert_eq!(rom_entries(&mut archive), ["b.nes", "a.NES"]);
+
+        let (name, data) = read_zip(&mut zip, None).unwrap();
+        assert_eq!((name.as_str(), data.as_slice()), ("b.nes", &b"NES2"[..]));
+        let (_, data) = read_zip(&mut zip, Some("a.NES")).unwrap();
+        assert_eq!(data, b"NES1");
+    }
+}
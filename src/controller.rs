@@ -1,4 +1,4 @@
-use crate::{bus::Device, util};
+use crate::{bus::Device, savestate::snapshot_fields, util};
 use std::sync::mpsc::Receiver;
 use tartan_bitfield::bitfield;
 
@@ -39,6 +39,9 @@ pub struct Controller {
     index: u8,
 }
 
+// The buttons are left out, they are whatever the player is holding now
+snapshot_fields!(Controller { strobe, index });
+
 impl Controller {
     pub fn new(button_receiver: Receiver<Buttons>) -> Self {
         let span = tracing::span!(tracing::Level::INFO, "controller");
@@ -102,8 +102,9 @@ mod instrs {
 
     pub fn inc(cpu: &mut Cpu, mode: &AddressingMode) {
         let addr = mode.fetch_param_address(cpu).0;
-        let value = cpu.read_byte(addr).wrapping_add(1);
-        cpu.write_byte(addr, value);
+        let original = cpu.read_byte(addr);
+        let value = original.wrapping_add(1);
+        cpu.write_modified(addr, original, value);
         cpu.update_zero_and_negative_flags(value);
     }
 
@@ -169,8 +170,9 @@ mod instrs {
 
     pub fn dec(cpu: &mut Cpu, mode: &AddressingMode) {
         let addr = mode.fetch_param_address(cpu).0;
-        let value = cpu.read_byte(addr).wrapping_sub(1);
-        cpu.write_byte(addr, value);
+        let original = cpu.read_byte(addr);
+        let value = original.wrapping_sub(1);
+        cpu.write_modified(addr, original, value);
         cpu.update_zero_and_negative_flags(value);
     }
 
@@ -305,11 +307,12 @@ mod instrs {
             cpu.accumulator
         } else {
             let addr = mode.fetch_param_address(cpu).0;
-            let mut value = cpu.read_byte(addr);
+            let original = cpu.read_byte(addr);
+            let mut value = original;
 
             cpu.flags.set_carry(util::nth_bit(value, 0));
             value >>= 1;
-            cpu.write_byte(addr, value);
+            cpu.write_modified(addr, original, value);
             value
         };
 
@@ -323,11 +326,12 @@ mod instrs {
             cpu.accumulator
         } else {
             let addr = mode.fetch_param_address(cpu).0;
-            let mut value = cpu.read_byte(addr);
+            let original = cpu.read_byte(addr);
+            let mut value = original;
 
             cpu.flags.set_carry(util::nth_bit(value, 7));
             value <<= 1;
-            cpu.write_byte(addr, value);
+            cpu.write_modified(addr, original, value);
             value
         };
 
@@ -344,11 +348,12 @@ mod instrs {
             cpu.accumulator
         } else {
             let addr = mode.fetch_param_address(cpu).0;
-            let mut value = cpu.read_byte(addr);
+            let original = cpu.read_byte(addr);
+            let mut value = original;
 
             cpu.flags.set_carry(util::nth_bit(value, 0));
             value = rotate_right(value);
-            cpu.write_byte(addr, value);
+            cpu.write_modified(addr, original, value);
             value
         };
 
@@ -365,11 +370,12 @@ mod instrs {
             cpu.accumulator
         } else {
             let addr = mode.fetch_param_address(cpu).0;
-            let mut value = cpu.read_byte(addr);
+            let original = cpu.read_byte(addr);
+            let mut value = original;
 
             cpu.flags.set_carry(util::nth_bit(value, 7));
             value = rotate_left(value);
-            cpu.write_byte(addr, value);
+            cpu.write_modified(addr, original, value);
             value
         };
 
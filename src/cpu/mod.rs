@@ -5,6 +5,7 @@ mod instructions;
 use self::flags::CpuFlags;
 use crate::{
     bus::{Bus, Clock, CycleCount, Device, Memory},
+    savestate::{snapshot_bitfield, snapshot_fields},
     util,
 };
 pub use addressing_mode::AddressingMode;
@@ -19,6 +20,8 @@ pub struct CpuRam {
     pub data: [u8; Self::SIZE],
 }
 
+snapshot_fields!(CpuRam { data });
+
 impl CpuRam {
     pub const SIZE: usize = 0x800;
 
@@ -70,14 +73,30 @@ pub struct Cpu {
     pub stack_pointer: u8,
     pub flags: CpuFlags,
     pub bus: Bus,
+    nmi_pending: bool,
+    irq_pending: bool,
 }
 
+snapshot_bitfield!(CpuFlags: u8);
+snapshot_fields!(Cpu {
+    accumulator,
+    register_x,
+    register_y,
+    program_counter,
+    stack_pointer,
+    flags,
+    nmi_pending,
+    irq_pending,
+    bus,
+});
+
 impl Cpu {
     const STACK_OFFSET: u16 = 0x0100;
     const STACK_RESET: u8 = 0xFD;
 
     const NMI_VECTOR: u16 = 0xFFFA;
     const RESET_VECTOR: u16 = 0xFFFC;
+    /// Shared between BRK and IRQ
     pub const BREAK_VECTOR: u16 = 0xFFFE;
 
     pub fn new(bus: Bus) -> Cpu {
@@ -90,6 +109,8 @@ impl Cpu {
             register_x: 0,
             register_y: 0,
             bus,
+            nmi_pending: false,
+            irq_pending: false,
         }
     }
 
@@ -102,6 +123,8 @@ impl Cpu {
         self.accumulator = 0;
         self.register_x = 0;
         self.register_y = 0;
+        self.nmi_pending = false;
+        self.irq_pending = false;
         self.program_counter = self.read_word(Cpu::RESET_VECTOR);
         tracing::info!("initialising, PC={:04X}", self.program_counter);
     }
@@ -139,10 +162,17 @@ impl Cpu {
         self.flags.set_zero(value == 0);
     }
 
-    #[tracing::instrument(skip(self), parent = &self.span)]
-    pub fn non_maskable_interrupt(&mut self) {
-        tracing::info!("NMI triggered");
+    /// Read-modify-write instructions write the unmodified value back before the result, which
+    /// registers with side effects notice
+    pub fn write_modified(&mut self, address: u16, original: u8, result: u8) {
+        self.write_byte(address, original);
+        self.write_byte(address, result);
+    }
+
+    /// Push the program counter and flags, then jump to the handler
+    fn interrupt(&mut self, vector: u16) {
         let mut flags = self.flags;
+        // See https://www.nesdev.org/wiki/Status_flags#The_B_flag
         flags.set_break_1(false);
         flags.set_break_2(true);
 
@@ -150,14 +180,28 @@ impl Cpu {
         self.push_byte(flags.into());
 
         self.flags.set_interrupts_disabled(true);
-        self.program_counter = self.read_word(Self::NMI_VECTOR);
-        self.tick(2);
+        self.program_counter = self.read_word(vector);
+        self.tick(7);
+    }
+
+    #[tracing::instrument(skip(self), parent = &self.span)]
+    pub fn non_maskable_interrupt(&mut self) {
+        tracing::info!("NMI triggered");
+        self.interrupt(Self::NMI_VECTOR);
+    }
+
+    #[tracing::instrument(skip(self), parent = &self.span)]
+    pub fn interrupt_request(&mut self) {
+        tracing::debug!("IRQ triggered");
+        self.interrupt(Self::BREAK_VECTOR);
     }
 
     #[tracing::instrument(skip(self), parent = &self.span)]
     pub fn step(&mut self) -> Option<CpuState> {
-        if self.bus.ppu.poll_nmi() {
+        if std::mem::take(&mut self.nmi_pending) {
             self.non_maskable_interrupt();
+        } else if self.irq_pending {
+            self.interrupt_request();
         }
 
         let opcode = self.read_byte(self.program_counter);
@@ -181,6 +225,8 @@ impl Cpu {
 
         tracing::debug!("{}  {}", self, state.instruction);
 
+        let interrupts_disabled = self.flags.interrupts_disabled();
+        self.bus.start_instruction(*cycles);
         (instr.function)(self, mode);
         if !instr.changes_program_counter {
             // Some instructions (e.g. JMP) set the program counter themselves
@@ -192,7 +238,17 @@ impl Cpu {
             return None;
         }
 
-        self.tick(*cycles);
+        self.nmi_pending = self.bus.finish_instruction();
+
+        // Interrupts are polled before the last cycle of an instruction. CLI, SEI and PLP change the flag
+        // after that, so their effect is delayed by one instruction. See https://www.nesdev.org/wiki/CPU_interrupts
+        let interrupts_disabled = if matches!(instr.name, "CLI" | "SEI" | "PLP") {
+            interrupts_disabled
+        } else {
+            self.flags.interrupts_disabled()
+        };
+        self.irq_pending = !interrupts_disabled && self.bus.poll_irq();
+
         Some(state)
     }
 }
@@ -230,7 +286,7 @@ impl fmt::Display for Cpu {
     }
 }
 
-/// Passed to the GUI for the debugger. Could maybe be used for savestates in the future?
+/// Passed to the GUI for the debugger, save states are handled by `savestate` instead
 pub struct CpuState {
     pub instruction: String,
     pub accumulator: u8,
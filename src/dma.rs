@@ -0,0 +1,67 @@
+//! The DMA unit of the 2A03, which halts the CPU to copy sprites into OAM and to fetch DMC samples.
+//! It reads on "get" cycles and writes on "put" cycles, which alternate.
+//! https://www.nesdev.org/wiki/DMA
+
+use crate::bus::CycleCount;
+
+#[derive(Default)]
+pub struct Dma {
+    /// The page written to $4014, copied once the current instruction is done
+    oam_page: Option<u8>,
+    /// A DMC fetch takes the place of cycles of a running OAM DMA
+    oam_running: bool,
+    /// Whether the CPU is halted for a DMC fetch
+    pub dmc_running: bool,
+}
+
+impl Dma {
+    pub const fn is_get_cycle(cycle: CycleCount) -> bool {
+        cycle.is_multiple_of(2)
+    }
+
+    pub fn request_oam(&mut self, page: u8) {
+        self.oam_page = Some(page);
+    }
+
+    /// The page to copy, if an OAM DMA was requested
+    pub fn start_oam(&mut self) -> Option<u8> {
+        let page = self.oam_page.take();
+        self.oam_running = page.is_some();
+        page
+    }
+
+    pub fn finish_oam(&mut self) {
+        self.oam_running = false;
+    }
+
+    /// The cycles the CPU is halted for before a DMC fetch that starts at the given cycle can read
+    pub const fn dmc_wait(&self, cycle: CycleCount) -> CycleCount {
+        // On its own it needs a halt and a dummy cycle, an OAM DMA has already halted the CPU
+        let wait = if self.oam_running { 1 } else { 2 };
+        // The read has to happen on a get cycle
+        if Self::is_get_cycle(cycle + wait) {
+            wait
+        } else {
+            wait + 1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dmc_wait() {
+        let mut dma = Dma::default();
+        // Three or four cycles in total, including the read
+        assert_eq!(dma.dmc_wait(0), 2);
+        assert_eq!(dma.dmc_wait(1), 3);
+
+        // Usually two during an OAM DMA
+        dma.request_oam(0x02);
+        assert_eq!(dma.start_oam(), Some(0x02));
+        assert_eq!(dma.dmc_wait(1), 1);
+        assert_eq!(dma.dmc_wait(0), 2);
+    }
+}
@@ -2,13 +2,21 @@
 // The idea is to make this generic in the future, so that other GUI frameworks can be used.
 // It would also be nice to make the GUI emulator-agnostic, but that requires more work.
 
-use crate::cheat::{CheatReceiver, CheatRequest};
+use crate::{
+    apu::mixer::AudioRequest,
+    cartridge::{rom::RomRequest, CartridgeError},
+    cheat::{CheatReceiver, CheatRequest},
+    ppu::VideoRequest,
+    region::Region,
+    rewind::Rewind,
+    savestate::{self, SaveStateRequest},
+};
 
 use {
-    crate::{bus, controller, cpu, ppu, LogReloadHandle},
-    std::{
-        path::PathBuf,
-        sync::mpsc::{channel, Receiver, Sender},
+    crate::{apu, bus, controller, cpu, ppu, LogReloadHandle},
+    std::sync::{
+        mpsc::{channel, Receiver, Sender, TryRecvError},
+        Arc,
     },
 };
 
@@ -42,34 +50,87 @@ impl StepState {
 pub struct CpuCommunication {
     button_receiver: Receiver<controller::Buttons>,
     pixel_sender: ppu::PixelSender,
+    sample_sender: Option<apu::SampleSender>,
+    sample_buffer_level: Arc<apu::SampleBufferLevel>,
     cpu_state_sender: Option<Sender<cpu::CpuState>>,
     step_receiver: Option<Receiver<StepState>>,
     reboot_receiver: Option<Receiver<()>>,
     cheat_receiver: Option<CheatReceiver>,
+    audio_receiver: Option<Receiver<AudioRequest>>,
+    video_receiver: Option<Receiver<VideoRequest>>,
+    track_receiver: Option<Receiver<u8>>,
+    save_state_receiver: Option<Receiver<SaveStateRequest>>,
+    /// Whether the rewind key is held
+    rewind_receiver: Option<Receiver<bool>>,
+
+    rom_receiver: Receiver<RomRequest>,
+    /// Reports ROMs that failed to load, without it the thread exits instead
+    rom_error_sender: Option<Sender<CartridgeError>>,
 
-    // TODO: switch to byte array receiver
-    rom_receiver: Receiver<PathBuf>,
-    unload_rom_receiver: Receiver<()>,
+    max_frames: Option<usize>,
+    region: Option<Region>,
 }
 
 impl CpuCommunication {
-    pub fn spawn(self) -> std::thread::JoinHandle<()> {
+    /// Stop emulating after the given amount of frames, running as fast as possible
+    pub fn with_max_frames(mut self, frames: usize) -> Self {
+        self.max_frames = Some(frames);
+        self
+    }
+
+    /// Run at the timing of the given region, whatever the cartridge asks for
+    pub fn with_region(mut self, region: Region) -> Self {
+        self.region = Some(region);
+        self
+    }
+
+    /// The thread returns the error of a ROM that failed to load when there is no GUI to report it to
+    pub fn spawn(self) -> std::thread::JoinHandle<Result<(), CartridgeError>> {
         std::thread::spawn(move || {
             let bus = bus::Bus::new(
                 self.button_receiver,
                 self.pixel_sender,
-                self.rom_receiver,
+                (self.sample_sender, self.sample_buffer_level),
                 self.cheat_receiver,
+                self.audio_receiver,
+                self.video_receiver,
             );
 
             let mut cpu = cpu::Cpu::new(bus);
+            cpu.bus.region_override = self.region;
+            cpu.bus.throttle = self.max_frames.is_none();
             let mut step_state = StepState::default();
             let mut inserted_cartridge = false;
 
+            // Only kept when there is someone to hold the rewind key
+            let mut rewind = self.rewind_receiver.as_ref().map(|_| Rewind::new());
+            let mut rewinding = false;
+            let mut last_frame = 0;
+
             loop {
-                if self.unload_rom_receiver.try_recv().is_ok() {
-                    inserted_cartridge = false;
-                    cpu.bus.unload_cartridge();
+                match self.rom_receiver.try_recv() {
+                    Ok(RomRequest::Load(rom)) => {
+                        inserted_cartridge = false;
+                        if let Err(err) = cpu.bus.load_rom(rom) {
+                            tracing::error!("failed to load ROM: {err}");
+                            let Some(rom_error_sender) = self.rom_error_sender.as_ref() else {
+                                return Err(err);
+                            };
+                            if rom_error_sender.send(err).is_err() {
+                                tracing::error!("failed to send ROM error, exiting cpu thread");
+                                break;
+                            }
+                        }
+                    }
+                    Ok(RomRequest::Unload) => {
+                        inserted_cartridge = false;
+                        cpu.bus.unload_cartridge();
+                    }
+                    Err(TryRecvError::Disconnected) => {
+                        tracing::info!("UI has closed, exiting cpu thread");
+                        break;
+                    }
+                    Err(TryRecvError::Empty) => {}
                 }
 
                 if !cpu.bus.has_cartridge() {
@@ -78,6 +139,13 @@ impl CpuCommunication {
                 } else if !inserted_cartridge {
                     inserted_cartridge = true;
                     cpu.reset();
+                    rewind.iter_mut().for_each(Rewind::clear);
+                }
+
+                if self.max_frames.is_some_and(|max| cpu.bus.frames >= max) {
+                    tracing::info!("emulated {} frames, exiting cpu thread", cpu.bus.frames);
+                    cpu.bus.apu.flush_samples();
+                    break;
                 }
 
                 if let Some(reboot_receiver) = self.reboot_receiver.as_ref() {
@@ -86,6 +154,38 @@ impl CpuCommunication {
                     }
                 }
 
+                if let Some(track_receiver) = self.track_receiver.as_ref() {
+                    if let Ok(track) = track_receiver.try_recv() {
+                        cpu.bus.select_track(track);
+                        cpu.reset();
+                    }
+                }
+
+                if let Some(save_state_receiver) = self.save_state_receiver.as_ref() {
+                    if let Ok(request) = save_state_receiver.try_recv() {
+                        savestate::handle_request(&mut cpu, request);
+                    }
+                }
+
+                if let Some(rewind_receiver) = self.rewind_receiver.as_ref() {
+                    if let Some(held) = rewind_receiver.try_iter().last() {
+                        rewinding = held;
+                    }
+                }
+
+                // Going back one frame and then emulating it draws the frames in reverse
+                if let Some(rewind) = rewind.as_mut() {
+                    if cpu.bus.frames != last_frame {
+                        if !rewinding {
+                            rewind.push(&cpu);
+                        } else if !rewind.pop(&mut cpu) {
+                            rewinding = false;
+                            rewind.push(&cpu);
+                        }
+                        last_frame = cpu.bus.frames;
+                    }
+                }
+
                 if let Some(step_receiver) = self.step_receiver.as_ref() {
                     if let Ok(new_step_state) = step_receiver.try_recv() {
                         step_state = new_step_state;
@@ -115,6 +215,10 @@ impl CpuCommunication {
                     break;
                 }
             }
+
+            // Don't lose the progress of games that save with a battery
+            cpu.bus.save_battery_ram();
+            Ok(())
         })
     }
 }
@@ -122,16 +226,23 @@ impl CpuCommunication {
 pub struct UiCommunication {
     pub button_sender: Sender<controller::Buttons>,
     pub pixel_receiver: ppu::PixelReceiver,
+    pub sample_receiver: Option<apu::SampleReceiver>,
+    pub sample_buffer_level: Arc<apu::SampleBufferLevel>,
     pub cpu_state_receiver: Option<Receiver<cpu::CpuState>>,
     pub log_reload_handle: LogReloadHandle,
 
     pub step_sender: Option<Sender<StepState>>,
     pub reboot_sender: Option<Sender<()>>,
 
-    pub rom_sender: Sender<PathBuf>,
-    pub unload_rom_sender: Sender<()>,
+    pub rom_sender: Sender<RomRequest>,
+    pub rom_error_receiver: Option<Receiver<CartridgeError>>,
 
     pub cheat_sender: Option<Sender<CheatRequest>>,
+    pub audio_sender: Option<Sender<AudioRequest>>,
+    pub video_sender: Option<Sender<VideoRequest>>,
+    pub track_sender: Option<Sender<u8>>,
+    pub save_state_sender: Option<Sender<SaveStateRequest>>,
+    pub rewind_sender: Option<Sender<bool>>,
 }
 
 pub trait EmulatorUi {
@@ -140,12 +251,20 @@ pub trait EmulatorUi {
 
 pub fn init(
     with_gui: bool,
+    with_audio: bool,
     log_reload_handle: LogReloadHandle,
 ) -> (CpuCommunication, UiCommunication) {
     let (rom_sender, rom_receiver) = channel();
-    let (unload_rom_sender, unload_rom_receiver) = channel();
     let (pixel_sender, pixel_receiver) = channel();
     let (button_sender, button_receiver) = channel();
+    let sample_buffer_level = Arc::new(apu::SampleBufferLevel::default());
+
+    let (sample_sender, sample_receiver) = if with_audio {
+        let (sample_sender, sample_receiver) = channel();
+        (Some(sample_sender), Some(sample_receiver))
+    } else {
+        (None, None)
+    };
 
     let (step_sender, step_receiver) = if with_gui {
         let (step_sender, step_receiver) = channel();
@@ -175,23 +294,81 @@ pub fn init(
         (None, None)
     };
 
+    let (audio_sender, audio_receiver) = if with_gui {
+        let (audio_sender, audio_receiver) = channel();
+        (Some(audio_sender), Some(audio_receiver))
+    } else {
+        (None, None)
+    };
+
+    let (video_sender, video_receiver) = if with_gui {
+        let (video_sender, video_receiver) = channel();
+        (Some(video_sender), Some(video_receiver))
+    } else {
+        (None, None)
+    };
+
+    let (track_sender, track_receiver) = if with_gui {
+        let (track_sender, track_receiver) = channel();
+        (Some(track_sender), Some(track_receiver))
+    } else {
+        (None, None)
+    };
+
+    let (save_state_sender, save_state_receiver) = if with_gui {
+        let (save_state_sender, save_state_receiver) = channel();
+        (Some(save_state_sender), Some(save_state_receiver))
+    } else {
+        (None, None)
+    };
+
+    let (rewind_sender, rewind_receiver) = if with_gui {
+        let (rewind_sender, rewind_receiver) = channel();
+        (Some(rewind_sender), Some(rewind_receiver))
+    } else {
+        (None, None)
+    };
+
+    let (rom_error_sender, rom_error_receiver) = if with_gui {
+        let (rom_error_sender, rom_error_receiver) = channel();
+        (Some(rom_error_sender), Some(rom_error_receiver))
+    } else {
+        (None, None)
+    };
+
     let cpu_comm = CpuCommunication {
         rom_receiver,
-        unload_rom_receiver,
+        rom_error_sender,
         button_receiver,
         pixel_sender,
+        sample_sender,
+        sample_buffer_level: sample_buffer_level.clone(),
         cpu_state_sender,
         step_receiver,
         reboot_receiver,
         cheat_receiver,
+        audio_receiver,
+        video_receiver,
+        track_receiver,
+        save_state_receiver,
+        rewind_receiver,
+        max_frames: None,
+        region: None,
     };
 
     let ui_comm = UiCommunication {
         cheat_sender,
+        audio_sender,
+        video_sender,
+        track_sender,
+        save_state_sender,
+        rewind_sender,
         rom_sender,
-        unload_rom_sender,
+        rom_error_receiver,
         button_sender,
         pixel_receiver,
+        sample_receiver,
+        sample_buffer_level,
         cpu_state_receiver,
         step_sender,
         reboot_sender,
@@ -0,0 +1,113 @@
+use crate::apu::{SampleBufferLevel, SampleReceiver, SAMPLE_BUFFER_TARGET, SAMPLE_RATE};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use std::{collections::VecDeque, sync::Arc};
+
+/// Plays the samples generated by the APU on the default audio device of the host.
+pub struct Audio {
+    // Playback stops when the stream is dropped
+    _stream: Option<cpal::Stream>,
+}
+
+impl Audio {
+    /// The most samples we will hold on to, anything beyond is dropped to bound the latency
+    const MAX_BUFFERED: usize = SAMPLE_BUFFER_TARGET * 4;
+
+    pub fn new(receiver: Option<SampleReceiver>, buffer_level: Arc<SampleBufferLevel>) -> Self {
+        let _span = tracing::span!(tracing::Level::INFO, "audio").entered();
+        let stream = receiver.and_then(|receiver| {
+            Self::start_stream(receiver, buffer_level.clone())
+                .map_err(|err| tracing::error!("failed to start audio output: {err}"))
+                .ok()
+        });
+
+        if stream.is_some() {
+            buffer_level.enable();
+            tracing::info!("started audio output at {SAMPLE_RATE} Hz");
+        } else {
+            tracing::info!("audio output disabled");
+        }
+
+        Self { _stream: stream }
+    }
+
+    fn start_stream(
+        receiver: SampleReceiver,
+        buffer_level: Arc<SampleBufferLevel>,
+    ) -> Result<cpal::Stream, String> {
+        let device = cpal::default_host()
+            .default_output_device()
+            .ok_or("no output device available")?;
+
+        let supported_config = device
+            .supported_output_configs()
+            .map_err(|err| err.to_string())?
+            .find(|config| {
+                config.min_sample_rate().0 <= SAMPLE_RATE
+                    && config.max_sample_rate().0 >= SAMPLE_RATE
+            })
+            .ok_or(format!("no output configuration supports {SAMPLE_RATE} Hz"))?
+            .with_sample_rate(cpal::SampleRate(SAMPLE_RATE));
+
+        let sample_format = supported_config.sample_format();
+        let config = supported_config.config();
+
+        let stream = match sample_format {
+            cpal::SampleFormat::F32 => {
+                Self::build_stream::<f32>(&device, &config, receiver, buffer_level)
+            }
+            cpal::SampleFormat::I16 => {
+                Self::build_stream::<i16>(&device, &config, receiver, buffer_level)
+            }
+            cpal::SampleFormat::U16 => {
+                Self::build_stream::<u16>(&device, &config, receiver, buffer_level)
+            }
+            format => return Err(format!("unsupported sample format {format}")),
+        }?;
+
+        stream.play().map_err(|err| err.to_string())?;
+        Ok(stream)
+    }
+
+    fn build_stream<T>(
+        device: &cpal::Device,
+        config: &cpal::StreamConfig,
+        receiver: SampleReceiver,
+        buffer_level: Arc<SampleBufferLevel>,
+    ) -> Result<cpal::Stream, String>
+    where
+        T: cpal::SizedSample + cpal::FromSample<f32>,
+    {
+        let channels = config.channels as usize;
+        let mut buffer = VecDeque::with_capacity(Self::MAX_BUFFERED);
+        let error_buffer_level = buffer_level.clone();
+
+        device
+            .build_output_stream(
+                config,
+                move |data: &mut [T], _| {
+                    for samples in receiver.try_iter() {
+                        buffer.extend(samples);
+                    }
+
+                    if buffer.len() > Self::MAX_BUFFERED {
+                        buffer.drain(..buffer.len() - Self::MAX_BUFFERED);
+                    }
+
+                    // The APU outputs mono audio, so every channel gets the same sample
+                    for frame in data.chunks_mut(channels) {
+                        let sample = T::from_sample(buffer.pop_front().unwrap_or_default());
+                        frame.fill(sample);
+                    }
+
+                    buffer_level.set(buffer.len());
+                },
+                move |err| {
+                    tracing::error!("audio stream error: {err}");
+                    // Fall back to timer based frame limiting, otherwise emulation would stall
+                    error_buffer_level.disable();
+                },
+                None,
+            )
+            .map_err(|err| err.to_string())
+    }
+}
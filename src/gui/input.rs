@@ -24,6 +24,28 @@ impl Input {
         ctx.input(|i| i.key_pressed(egui::Key::O))
     }
 
+    pub fn save_state(&self, ctx: &egui::Context) -> bool {
+        ctx.input(|i| i.key_pressed(egui::Key::F5))
+    }
+
+    pub fn load_state(&self, ctx: &egui::Context) -> bool {
+        ctx.input(|i| i.key_pressed(egui::Key::F7))
+    }
+
+    /// Rewinding lasts for as long as the key is held
+    pub fn rewind(&self, ctx: &egui::Context) -> bool {
+        ctx.input(|i| i.key_down(egui::Key::Backspace))
+    }
+
+    /// The number keys pick a save state slot
+    pub fn select_slot(&self, ctx: &egui::Context) -> Option<u8> {
+        use egui::Key::*;
+        const KEYS: [egui::Key; 10] = [Num0, Num1, Num2, Num3, Num4, Num5, Num6, Num7, Num8, Num9];
+
+        ctx.input(|i| KEYS.iter().position(|&key| i.key_pressed(key)))
+            .map(|slot| slot as u8)
+    }
+
     // TODO: This is very, very ugly
     #[tracing::instrument(skip(self, ctx), parent = &self.span)]
     pub fn update(&self, ctx: &egui::Context) {
@@ -1,22 +1,41 @@
-use crate::cheat::{Cheat, CheatRequest};
+use crate::{
+    apu::mixer::{AudioChannel, AudioRequest, ChannelSettings},
+    cartridge::{
+        nsf::Nsf,
+        rom::{self, Rom, RomRequest},
+        CartridgeError,
+    },
+    cheat::{Cheat, CheatRequest},
+    ppu::palette::PaletteChoice,
+    savestate::{self, SaveStateRequest},
+    settings::{Settings, NTSC_FILTER_SETTING, PALETTE_SETTING},
+};
 
+mod audio;
 mod cpu_debugger;
 mod input;
 mod screen;
 
 use {
-    self::{cpu_debugger::CpuDebugger, input::Input, screen::Screen},
+    self::{audio::Audio, cpu_debugger::CpuDebugger, input::Input, screen::Screen},
     crate::{
+        apu::{SampleBufferLevel, SampleReceiver},
         controller,
         cpu::CpuState,
         glue::StepState,
-        ppu::renderer::{PixelBuffer, HEIGHT, WIDTH},
+        ppu::{
+            renderer::{HEIGHT, WIDTH},
+            PixelReceiver, VideoRequest,
+        },
         LogReloadHandle,
     },
     eframe::egui,
     std::{
-        path::PathBuf,
-        sync::mpsc::{Receiver, Sender},
+        path::{Path, PathBuf},
+        sync::{
+            mpsc::{Receiver, Sender},
+            Arc,
+        },
     },
     tracing::metadata::LevelFilter,
     tracing_subscriber::EnvFilter,
@@ -34,29 +53,60 @@ pub struct Gui {
     cpu_debugger: CpuDebugger,
     current_view: View,
     input: Input,
+    _audio: Audio,
 
-    rom_sender: Sender<PathBuf>,
-    unload_rom_sender: Sender<()>,
+    rom_sender: Sender<RomRequest>,
     reboot_sender: Sender<()>,
+    rom_error_receiver: Receiver<CartridgeError>,
+    /// Why the last ROM failed to load, shown until dismissed
+    rom_error: Option<String>,
+    /// An archive holding several ROMs, the user picks which one to load
+    archive_entries: Option<(PathBuf, Vec<String>)>,
 
     log_reload_handle: LogReloadHandle,
     log_level: LevelFilter,
 
     cheat_sender: Sender<CheatRequest>,
     cheats: Vec<(bool, Cheat)>,
+
+    audio_sender: Sender<AudioRequest>,
+    audio_channels: [ChannelSettings; AudioChannel::ALL.len()],
+
+    video_sender: Sender<VideoRequest>,
+    sprite_limit: bool,
+    palette: PaletteChoice,
+    ntsc_filter: bool,
+
+    settings: Settings,
+
+    track_sender: Sender<u8>,
+    /// The music file that is loaded, if any, and the track that is playing
+    nsf: Option<(Nsf, u8)>,
+
+    save_state_sender: Sender<SaveStateRequest>,
+    save_state_slot: u8,
+
+    rewind_sender: Sender<bool>,
+    rewinding: bool,
 }
 
 impl Gui {
+    const PALETTE_EXTENSIONS: [&'static str; 1] = ["pal"];
+
     #[allow(clippy::too_many_arguments)] // TODO: fix this
     pub fn run(
         window_title: &str,
         log_reload_handle: LogReloadHandle,
         cpu_state_receiver: Receiver<CpuState>,
         button_sender: Sender<controller::Buttons>,
-        pixel_receiver: Receiver<Box<PixelBuffer>>,
+        pixel_receiver: PixelReceiver,
+        (sample_receiver, sample_buffer_level): (Option<SampleReceiver>, Arc<SampleBufferLevel>),
         cheat_sender: Sender<CheatRequest>,
+        (audio_sender, video_sender): (Sender<AudioRequest>, Sender<VideoRequest>),
+        track_sender: Sender<u8>,
+        (save_state_sender, rewind_sender): (Sender<SaveStateRequest>, Sender<bool>),
         (step_sender, reboot_sender): (Sender<StepState>, Sender<()>),
-        (rom_sender, unload_rom_sender): (Sender<PathBuf>, Sender<()>),
+        (rom_sender, rom_error_receiver): (Sender<RomRequest>, Receiver<CartridgeError>),
     ) {
         let span = tracing::span!(tracing::Level::INFO, "gui");
         let log_level = log_reload_handle
@@ -73,23 +123,59 @@ impl Gui {
             ..Default::default()
         };
 
-        let gui = Self {
+        let settings = Settings::load();
+        let palette = settings
+            .get(PALETTE_SETTING)
+            .and_then(|palette| {
+                palette
+                    .parse()
+                    .map_err(|err| tracing::error!("invalid palette setting: {err}"))
+                    .ok()
+            })
+            .unwrap_or_default();
+
+        let mut gui = Self {
             span,
             rom_sender,
             reboot_sender,
-            unload_rom_sender,
+            rom_error_receiver,
+            rom_error: None,
+            archive_entries: None,
             screen: Screen::new(pixel_receiver),
             cpu_debugger: CpuDebugger::new(cpu_state_receiver, step_sender),
             current_view: View::Screen,
             input: Input::new(button_sender),
+            _audio: Audio::new(sample_receiver, sample_buffer_level),
 
             cheat_sender,
             cheats: Vec::new(),
 
+            audio_sender,
+            audio_channels: Default::default(),
+
+            video_sender,
+            sprite_limit: true,
+            palette: PaletteChoice::default(),
+            ntsc_filter: settings.get(NTSC_FILTER_SETTING) == Some("true"),
+
+            settings,
+
+            track_sender,
+            nsf: None,
+
+            save_state_sender,
+            save_state_slot: 1,
+
+            rewind_sender,
+            rewinding: false,
+
             log_reload_handle,
             log_level,
         };
 
+        gui.set_palette(palette);
+        gui.send_video_request(VideoRequest::NtscFilter(gui.ntsc_filter));
+
         eframe::run_native(window_title, options, Box::new(|_cc| Box::new(gui))).unwrap_or_else(
             |err| {
                 tracing::error!("failed to run GUI: {}", err);
@@ -118,31 +204,143 @@ impl Gui {
         }
     }
 
-    fn send_rom_path(&mut self, path: PathBuf) {
+    /// Load a ROM file, asking which one to use first if it is an archive of several
+    fn open_rom_file(&mut self, path: PathBuf) {
+        match Rom::zip_entries(&path) {
+            Ok(entries) if entries.len() > 1 => self.archive_entries = Some((path, entries)),
+            Ok(_) => self.load_rom(&path, None),
+            Err(err) => {
+                tracing::error!("failed to open archive {}: {err}", path.display());
+                self.rom_error = Some(err.to_string());
+            }
+        }
+    }
+
+    fn load_rom(&mut self, path: &Path, entry: Option<&str>) {
         self.unload_rom(); // In case one is already loaded, does nothing otherwise
+        self.rom_error = None;
         tracing::info!("opening ROM file: {}", path.display());
-        self.rom_sender.send(path).unwrap_or_else(|err| {
-            tracing::error!("failed to send ROM path: {}", err);
+
+        let rom = match Rom::read(path, entry) {
+            Ok(rom) => rom,
+            Err(err) => {
+                tracing::error!("failed to open ROM {}: {err}", path.display());
+                self.rom_error = Some(err.to_string());
+                return;
+            }
+        };
+
+        // The track list is not known to the emulator thread, so we parse music files ourselves
+        self.nsf = Some(&rom.data)
+            .filter(|data| Nsf::is_nsf(data))
+            .and_then(|data| Nsf::from_bytes(data).ok())
+            .map(|nsf| {
+                let track = nsf.starting_song;
+                (nsf, track)
+            });
+
+        self.rom_sender
+            .send(RomRequest::Load(rom))
+            .unwrap_or_else(|err| {
+                tracing::error!("failed to send ROM: {}", err);
+            });
+    }
+
+    fn select_track(&mut self, track: u8) {
+        if let Some((nsf, current)) = &mut self.nsf {
+            *current = track;
+            tracing::info!("selecting track {}: {}", track + 1, nsf.track_title(track));
+            self.track_sender.send(track).unwrap_or_else(|err| {
+                tracing::error!("failed to send track selection: {err}");
+            });
+        }
+    }
+
+    fn send_save_state_request(&self, request: SaveStateRequest) {
+        self.save_state_sender.send(request).unwrap_or_else(|err| {
+            tracing::error!("failed to send save state request: {err}");
         });
     }
 
     fn unload_rom(&mut self) {
         tracing::info!("closing ROM");
-        self.unload_rom_sender.send(()).unwrap_or_else(|err| {
-            tracing::error!("failed to send unload ROM signal: {err}");
-        });
+        self.rom_sender
+            .send(RomRequest::Unload)
+            .unwrap_or_else(|err| {
+                tracing::error!("failed to send unload ROM signal: {err}");
+            });
+        self.nsf = None;
         self.cpu_debugger.update_buffer();
         self.cpu_debugger.clear_states();
     }
 
+    /// Tell the user why the ROM they opened could not be loaded
+    fn rom_error_dialog(&mut self, ctx: &egui::Context) {
+        if let Some(err) = self.rom_error_receiver.try_iter().last() {
+            self.rom_error = Some(err.to_string());
+            self.nsf = None;
+        }
+
+        let Some(err) = &self.rom_error else {
+            return;
+        };
+
+        let mut dismissed = false;
+        egui::Window::new("Failed to open ROM")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+            .show(ctx, |ui| {
+                ui.label(err);
+                dismissed = ui.button("OK").clicked();
+            });
+
+        if dismissed {
+            self.rom_error = None;
+        }
+    }
+
+    /// Let the user pick a ROM from an archive that holds several
+    fn archive_dialog(&mut self, ctx: &egui::Context) {
+        let Some((path, entries)) = &self.archive_entries else {
+            return;
+        };
+
+        let mut chosen = None;
+        let mut cancelled = false;
+        egui::Window::new("Choose a ROM")
+            .collapsible(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+            .show(ctx, |ui| {
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    for entry in entries {
+                        if ui.button(entry).clicked() {
+                            chosen = Some(entry.clone());
+                        }
+                    }
+                });
+
+                ui.separator();
+                cancelled = ui.button("Cancel").clicked();
+            });
+
+        if let Some(entry) = chosen {
+            let path = path.clone();
+            self.archive_entries = None;
+            self.load_rom(&path, Some(&entry));
+        } else if cancelled {
+            self.archive_entries = None;
+        }
+    }
+
     fn update_dropped_files(&mut self, ctx: &egui::Context) {
-        for file in &ctx.input(|i| i.raw.dropped_files.iter().last().cloned()) {
+        if let Some(file) = &ctx.input(|i| i.raw.dropped_files.iter().last().cloned()) {
             if let Some(path) = &file.path {
-                if path.extension().unwrap_or_default() == "nes" {
-                    self.send_rom_path(path.to_path_buf());
+                if rom::is_supported(path) {
+                    self.open_rom_file(path.to_path_buf());
                 } else {
                     tracing::warn!(
-                        "dropped file '{}' does not have a .nes file extension! ignoring",
+                        "dropped file '{}' is not a ROM or an archive of one! ignoring",
                         path.display()
                     );
                 }
@@ -150,7 +348,7 @@ impl Gui {
         }
     }
 
-    fn menu_bar(&mut self, ui: &mut egui::Ui) {
+    fn menu_bar(&mut self, ui: &mut egui::Ui, frame: &mut eframe::Frame) {
         egui::menu::bar(ui, |ui| {
             ui.menu_button("File", |ui| {
                 let open_file = ui.button("Open").on_hover_text("Open a ROM file");
@@ -159,10 +357,11 @@ impl Gui {
                     self.cpu_debugger.pause();
 
                     if let Some(file) = rfd::FileDialog::new()
-                        .add_filter("NES ROM", &["nes"])
+                        .add_filter("NES ROM", &rom::ROM_EXTENSIONS)
+                        .add_filter("Archive", &rom::ARCHIVE_EXTENSIONS)
                         .pick_file()
                     {
-                        self.send_rom_path(file);
+                        self.open_rom_file(file);
                     }
 
                     self.cpu_debugger.unpause();
@@ -178,7 +377,8 @@ impl Gui {
                 if quit.clicked() {
                     ui.close_menu();
                     tracing::info!("quit button clicked, exiting");
-                    std::process::exit(0);
+                    // Closing the window lets the emulator thread save before exiting
+                    frame.close();
                 };
             });
 
@@ -210,6 +410,8 @@ impl Gui {
                 }
             });
 
+            ui.menu_button("State", |ui| self.save_state_menu(ui));
+
             ui.menu_button("Show", |ui| {
                 let screen = ui.radio_value(&mut self.current_view, View::Screen, "Screen");
                 if screen.clicked() {
@@ -286,13 +488,227 @@ impl Gui {
                 }
             });
 
+            ui.menu_button("Video", |ui| self.video_menu(ui));
+
+            ui.menu_button("Audio", |ui| {
+                for channel in AudioChannel::ALL {
+                    self.audio_channel_controls(ui, channel);
+                }
+
+                ui.separator();
+                if ui.button("Reset").clicked() {
+                    tracing::info!("resetting audio mixer");
+                    ui.close_menu();
+                    for channel in AudioChannel::ALL {
+                        self.audio_channels[channel as usize] = ChannelSettings::default();
+                        self.send_audio_request(AudioRequest::Mute(channel, false));
+                        self.send_audio_request(AudioRequest::Solo(channel, false));
+                        self.send_audio_request(AudioRequest::Volume(channel, 1.0));
+                    }
+                }
+            });
+
+            if self.nsf.is_some() {
+                ui.menu_button("Track", |ui| self.track_menu(ui));
+            }
+
             ui.menu_button("Log", |ui| {
                 self.log_level_button(ui, LevelFilter::ERROR);
                 self.log_level_button(ui, LevelFilter::WARN);
                 self.log_level_button(ui, LevelFilter::INFO);
                 self.log_level_button(ui, LevelFilter::DEBUG);
                 self.log_level_button(ui, LevelFilter::TRACE);
-            })
+            });
+
+            if let Some((nsf, track)) = &self.nsf {
+                ui.separator();
+                ui.label(format!("{}: {}", nsf.title, nsf.track_title(*track)));
+            }
+        });
+    }
+
+    fn save_state_menu(&mut self, ui: &mut egui::Ui) {
+        let save = ui
+            .button("Save (F5)")
+            .on_hover_text("Save the state of the emulator to the selected slot");
+        if save.clicked() {
+            ui.close_menu();
+            self.send_save_state_request(SaveStateRequest::Save(self.save_state_slot));
+        }
+
+        let load = ui
+            .button("Load (F7)")
+            .on_hover_text("Restore the state of the emulator from the selected slot");
+        if load.clicked() {
+            ui.close_menu();
+            self.send_save_state_request(SaveStateRequest::Load(self.save_state_slot));
+        }
+
+        ui.separator();
+        for slot in 0..savestate::SLOTS {
+            ui.radio_value(&mut self.save_state_slot, slot, format!("Slot {slot}"));
+        }
+    }
+
+    fn track_menu(&mut self, ui: &mut egui::Ui) {
+        let Some((nsf, current)) = &self.nsf else {
+            return;
+        };
+        let mut selected = *current;
+
+        ui.label(egui::RichText::new(&nsf.title).strong());
+        for info in [&nsf.artist, &nsf.copyright] {
+            if !info.is_empty() {
+                ui.label(info);
+            }
+        }
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            let previous = ui.add_enabled(selected > 0, egui::Button::new("Previous"));
+            if previous.clicked() {
+                selected -= 1;
+            }
+
+            let next = ui.add_enabled(selected + 1 < nsf.total_songs, egui::Button::new("Next"));
+            if next.clicked() {
+                selected += 1;
+            }
+        });
+
+        ui.separator();
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            for track in 0..nsf.total_songs {
+                let title = format!("{}. {}", track + 1, nsf.track_title(track));
+                if ui.radio_value(&mut selected, track, title).clicked() {
+                    ui.close_menu();
+                }
+            }
+        });
+
+        if selected != *current {
+            self.select_track(selected);
+        }
+    }
+
+    fn audio_channel_controls(&mut self, ui: &mut egui::Ui, channel: AudioChannel) {
+        let mut requests = Vec::new();
+        let settings = &mut self.audio_channels[channel as usize];
+
+        ui.horizontal(|ui| {
+            ui.label(channel.to_string());
+            if ui.checkbox(&mut settings.muted, "Mute").changed() {
+                requests.push(AudioRequest::Mute(channel, settings.muted));
+            }
+
+            if ui.checkbox(&mut settings.solo, "Solo").changed() {
+                requests.push(AudioRequest::Solo(channel, settings.solo));
+            }
+
+            let volume = egui::Slider::new(&mut settings.volume, 0.0..=1.0).show_value(false);
+            if ui.add(volume).on_hover_text("Volume").changed() {
+                requests.push(AudioRequest::Volume(channel, settings.volume));
+            }
+        });
+
+        for request in requests {
+            self.send_audio_request(request);
+        }
+    }
+
+    fn send_audio_request(&self, request: AudioRequest) {
+        self.audio_sender.send(request).unwrap_or_else(|err| {
+            tracing::error!("failed to send audio request: {err}");
+        });
+    }
+
+    fn video_menu(&mut self, ui: &mut egui::Ui) {
+        let sprite_limit = ui
+            .checkbox(&mut self.sprite_limit, "Sprite limit")
+            .on_hover_text("Draw at most 8 sprites per scanline, disable to reduce flicker");
+        if sprite_limit.changed() {
+            self.send_video_request(VideoRequest::SpriteLimit(self.sprite_limit));
+        }
+
+        let ntsc_filter = ui
+            .checkbox(&mut self.ntsc_filter, "NTSC filter")
+            .on_hover_text("Recreate the color artifacts of composite video");
+        if ntsc_filter.changed() {
+            self.send_video_request(VideoRequest::NtscFilter(self.ntsc_filter));
+            self.settings.set(NTSC_FILTER_SETTING, self.ntsc_filter);
+        }
+
+        ui.separator();
+        ui.label("Palette");
+
+        let built_in = ui.radio(self.palette == PaletteChoice::BuiltIn, "Built-in");
+        if built_in.clicked() {
+            self.set_palette(PaletteChoice::BuiltIn);
+        }
+
+        let measured = ui
+            .radio(self.palette == PaletteChoice::Measured2C02, "2C02")
+            .on_hover_text("Colors measured from the video output of a 2C02 PPU");
+        if measured.clicked() {
+            self.set_palette(PaletteChoice::Measured2C02);
+        }
+
+        let is_ntsc = matches!(self.palette, PaletteChoice::Ntsc { .. });
+        let ntsc = ui
+            .radio(is_ntsc, "NTSC")
+            .on_hover_text("Generate the colors from the NTSC signal");
+        if ntsc.clicked() && !is_ntsc {
+            self.set_palette(PaletteChoice::NTSC_DEFAULT);
+        }
+
+        if let PaletteChoice::Ntsc {
+            mut hue,
+            mut saturation,
+        } = self.palette
+        {
+            let hue_slider = ui.add(egui::Slider::new(&mut hue, -180.0..=180.0).text("Hue"));
+            let saturation_slider =
+                ui.add(egui::Slider::new(&mut saturation, 0.0..=2.0).text("Saturation"));
+            if hue_slider.changed() || saturation_slider.changed() {
+                self.set_palette(PaletteChoice::Ntsc { hue, saturation });
+            }
+        }
+
+        if let PaletteChoice::File(path) = &self.palette {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            let _ = ui.radio(true, name);
+        }
+
+        let open_file = ui
+            .button("Open .pal file")
+            .on_hover_text("Load the colors from a 192 or 1536 byte palette file");
+        if open_file.clicked() {
+            ui.close_menu();
+            if let Some(file) = rfd::FileDialog::new()
+                .add_filter("Palette", &Self::PALETTE_EXTENSIONS)
+                .pick_file()
+            {
+                self.set_palette(PaletteChoice::File(file));
+            }
+        }
+    }
+
+    /// Switch to another palette and remember it for the next session
+    fn set_palette(&mut self, palette: PaletteChoice) {
+        match palette.colors() {
+            Ok(colors) => {
+                tracing::info!("switching palette to {palette}");
+                self.send_video_request(VideoRequest::Palette(colors));
+                self.settings.set(PALETTE_SETTING, &palette);
+                self.palette = palette;
+            }
+            Err(err) => tracing::error!("failed to load palette: {err}"),
+        }
+    }
+
+    fn send_video_request(&self, request: VideoRequest) {
+        self.video_sender.send(request).unwrap_or_else(|err| {
+            tracing::error!("failed to send video request: {err}");
         });
     }
 
@@ -311,8 +727,8 @@ impl Gui {
 }
 
 impl eframe::App for Gui {
-    #[tracing::instrument(skip(self, ctx, _frame), parent = &self.span)]
-    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+    #[tracing::instrument(skip(self, ctx, frame), parent = &self.span)]
+    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
         // Not updating the buffers will cause a memory leak because the MPSC channels wont be emptied.
         // TODO: Switch to a bounded crossbeam channel to avoid this.
         self.input.update(ctx);
@@ -328,8 +744,29 @@ impl eframe::App for Gui {
             self.cpu_debugger.step();
         }
 
+        if let Some(slot) = self.input.select_slot(ctx) {
+            tracing::info!("selected save state slot {slot}");
+            self.save_state_slot = slot;
+        }
+
+        if self.input.save_state(ctx) {
+            self.send_save_state_request(SaveStateRequest::Save(self.save_state_slot));
+        }
+
+        if self.input.load_state(ctx) {
+            self.send_save_state_request(SaveStateRequest::Load(self.save_state_slot));
+        }
+
+        let rewinding = self.input.rewind(ctx);
+        if rewinding != self.rewinding {
+            self.rewinding = rewinding;
+            self.rewind_sender.send(rewinding).unwrap_or_else(|err| {
+                tracing::error!("failed to send rewind state: {err}");
+            });
+        }
+
         egui::TopBottomPanel::top("menu_bar").show(ctx, |ui| {
-            self.menu_bar(ui);
+            self.menu_bar(ui, frame);
         });
 
         egui::CentralPanel::default().show(ctx, |ui| match self.current_view {
@@ -341,6 +778,9 @@ impl eframe::App for Gui {
             }
         });
 
+        self.archive_dialog(ctx);
+        self.rom_error_dialog(ctx);
+
         // Calling this here will request another frame immediately after this one
         ctx.request_repaint();
     }
@@ -1,15 +1,14 @@
-use crate::ppu::renderer::{PixelBuffer, HEIGHT, WIDTH};
+use crate::ppu::PixelReceiver;
 use eframe::egui;
-use std::sync::mpsc::Receiver;
 
 /// The screen to show pixels generated by the PPU.
 pub struct Screen {
     texture: Option<egui::TextureHandle>,
-    receiver: Receiver<Box<PixelBuffer>>,
+    receiver: PixelReceiver,
 }
 
 impl Screen {
-    pub fn new(receiver: Receiver<Box<PixelBuffer>>) -> Self {
+    pub fn new(receiver: PixelReceiver) -> Self {
         Self {
             texture: None,
             receiver,
@@ -33,10 +32,10 @@ impl Screen {
 
     /// Update the internal texture with a pixel buffer, if the PPU has generated one.
     pub fn update_buffer(&mut self, ctx: &egui::Context) {
-        while let Some(buf) = self.receiver.try_iter().last() {
+        while let Some(frame) = self.receiver.try_iter().last() {
             self.texture = Some(ctx.load_texture(
                 "screen-with-pixels",
-                egui::ColorImage::from_rgb([WIDTH, HEIGHT], &*buf),
+                egui::ColorImage::from_rgb([frame.width, frame.height], &frame.pixels),
                 egui::TextureOptions::NEAREST,
             ));
         }
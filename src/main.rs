@@ -1,17 +1,29 @@
+mod apu;
 mod bus;
 mod cartridge;
 mod cheat;
 mod controller;
 mod cpu;
+mod dma;
 mod glue;
 mod gui;
 mod ppu;
+mod region;
+mod rewind;
+mod savestate;
+mod settings;
 mod util;
 
 use {
+    apu::wav::WavWriter,
+    cartridge::rom::{Rom, RomRequest},
     clap::Parser,
-    glue::EmulatorUi,
+    glue::{EmulatorUi, UiCommunication},
     gui::Gui,
+    ppu::palette::PaletteChoice,
+    region::Region,
+    settings::{Settings, PALETTE_SETTING},
+    std::{fs::File, io::BufWriter, path::PathBuf},
     tracing_subscriber::{
         filter::{LevelFilter, ParseError},
         fmt,
@@ -42,12 +54,33 @@ fn tracing_init(log_level: Option<String>) -> Result<LogReloadHandle, ParseError
 #[derive(Parser)]
 #[command(author = "IvarWithoutBones", about = "A NES emulator written in Rust.")]
 struct Args {
+    /// A ROM or music file, or a .zip or .gz archive holding one. The first ROM of a zip is used.
     #[arg(short, long)]
-    rom: Option<String>,
+    rom: Option<PathBuf>,
 
     #[arg(short, long)]
     without_gui: bool,
 
+    /// Disable audio output, the frame rate will be limited with a timer instead
+    #[arg(long)]
+    without_audio: bool,
+
+    /// Write the audio output to a WAV file, running as fast as possible until `--frames` have been emulated
+    #[arg(long, requires_all = ["without_gui", "rom", "frames"])]
+    record_audio: Option<PathBuf>,
+
+    /// Load the output colors from a 192 or 1536 byte .pal file, which is remembered for later sessions
+    #[arg(long)]
+    palette: Option<PathBuf>,
+
+    /// Run at the timing of this region instead of the one the ROM asks for
+    #[arg(long, value_enum)]
+    region: Option<Region>,
+
+    /// Stop after emulating this many frames
+    #[arg(long)]
+    frames: Option<usize>,
+
     // https://docs.rs/tracing-subscriber/0.3.16/tracing_subscriber/filter/struct.EnvFilter.html#example-syntax
     #[arg(short, long)]
     log_level: Option<String>,
@@ -61,13 +94,33 @@ impl EmulatorUi for Gui {
             ui.cpu_state_receiver.unwrap(),
             ui.button_sender,
             ui.pixel_receiver,
+            (ui.sample_receiver, ui.sample_buffer_level),
             ui.cheat_sender.unwrap(),
+            (ui.audio_sender.unwrap(), ui.video_sender.unwrap()),
+            ui.track_sender.unwrap(),
+            (ui.save_state_sender.unwrap(), ui.rewind_sender.unwrap()),
             (ui.step_sender.unwrap(), ui.reboot_sender.unwrap()),
-            (ui.rom_sender, ui.unload_rom_sender),
+            (ui.rom_sender, ui.rom_error_receiver.unwrap()),
         );
     }
 }
 
+/// Write all samples the emulator produces to a WAV file, until the CPU thread exits
+fn record_audio(ui: UiCommunication, path: PathBuf) -> std::io::Result<()> {
+    let file = BufWriter::new(File::create(&path)?);
+    let mut wav = WavWriter::new(file, apu::SAMPLE_RATE)?;
+
+    for samples in ui.sample_receiver.unwrap() {
+        wav.write_samples(&samples)?;
+        // Nothing displays the frames, drop them to avoid piling them up in memory
+        ui.pixel_receiver.try_iter().for_each(drop);
+    }
+
+    wav.finish()?;
+    tracing::info!("recorded audio to {}", path.display());
+    Ok(())
+}
+
 fn main() {
     let args = Args::parse();
     let log_reload_handle = tracing_init(args.log_level.clone()).unwrap_or_else(|err| {
@@ -78,18 +131,52 @@ fn main() {
         std::process::exit(1);
     });
 
-    let (cpu, ui) = glue::init(!args.without_gui, log_reload_handle);
+    if let Some(path) = args.palette {
+        // Remembered for later sessions, which can be started from another directory
+        let path = std::fs::canonicalize(&path).unwrap_or_else(|err| {
+            tracing::error!("failed to load palette {}: {err}", path.display());
+            std::process::exit(1);
+        });
+        let palette = PaletteChoice::File(path);
+        if let Err(err) = palette.colors() {
+            tracing::error!("failed to load palette: {err}");
+            std::process::exit(1);
+        }
+        // The GUI picks it up from the settings
+        Settings::load().set(PALETTE_SETTING, palette);
+    }
+
+    let with_audio = (!args.without_gui && !args.without_audio) || args.record_audio.is_some();
+    let (mut cpu, ui) = glue::init(!args.without_gui, with_audio, log_reload_handle);
+    if let Some(frames) = args.frames {
+        cpu = cpu.with_max_frames(frames);
+    }
+    if let Some(region) = args.region {
+        cpu = cpu.with_region(region);
+    }
     let cpu_handle = cpu.spawn();
 
-    if let Some(rom) = args.rom {
-        ui.rom_sender.send(rom.into()).unwrap();
+    if let Some(path) = args.rom {
+        let rom = Rom::read(&path, None).unwrap_or_else(|err| {
+            tracing::error!("failed to open ROM \"{}\": {err}", path.display());
+            std::process::exit(1);
+        });
+        ui.rom_sender.send(RomRequest::Load(rom)).unwrap();
     }
 
     if !args.without_gui {
         Gui::start_ui(ui);
+    } else if let Some(path) = args.record_audio {
+        record_audio(ui, path).unwrap_or_else(|err| {
+            tracing::error!("failed to record audio: {err}");
+            std::process::exit(1);
+        });
     }
 
-    if cpu_handle.join().is_err() {
-        tracing::error!("CPU thread panicked");
-    };
+    match cpu_handle.join() {
+        Ok(Ok(())) => {}
+        // Already logged by the CPU thread
+        Ok(Err(_)) => std::process::exit(1),
+        Err(_) => tracing::error!("CPU thread panicked"),
+    }
 }
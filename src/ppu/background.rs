@@ -0,0 +1,75 @@
+use crate::{savestate::snapshot_fields, util};
+
+/// The background half of the rendering pipeline. Each tile is fetched into latches during the
+/// eight dots before it is needed, then moved into shift registers that advance one pixel per dot.
+/// https://www.nesdev.org/wiki/PPU_rendering
+#[derive(Default)]
+pub struct Background {
+    pub tile_index: u8,
+    /// Palette of the tile, already selected from the quadrant of the attribute byte
+    pub attribute: u8,
+    pub pattern_low: u8,
+    pub pattern_high: u8,
+
+    pattern_shift_low: u16,
+    pattern_shift_high: u16,
+    attribute_shift_low: u16,
+    attribute_shift_high: u16,
+}
+
+snapshot_fields!(Background {
+    tile_index,
+    attribute,
+    pattern_low,
+    pattern_high,
+    pattern_shift_low,
+    pattern_shift_high,
+    attribute_shift_low,
+    attribute_shift_high,
+});
+
+impl Background {
+    /// Move the latched tile into the low byte of the shift registers, behind the tile being drawn
+    pub fn reload(&mut self) {
+        const fn expand(bit: bool) -> u16 {
+            if bit {
+                0xFF
+            } else {
+                0
+            }
+        }
+
+        self.pattern_shift_low = (self.pattern_shift_low & 0xFF00) | self.pattern_low as u16;
+        self.pattern_shift_high = (self.pattern_shift_high & 0xFF00) | self.pattern_high as u16;
+        // The palette covers the whole tile, so it is repeated for every pixel
+        self.attribute_shift_low =
+            (self.attribute_shift_low & 0xFF00) | expand(util::nth_bit(self.attribute, 0));
+        self.attribute_shift_high =
+            (self.attribute_shift_high & 0xFF00) | expand(util::nth_bit(self.attribute, 1));
+    }
+
+    pub fn shift(&mut self) {
+        self.pattern_shift_low <<= 1;
+        self.pattern_shift_high <<= 1;
+        self.attribute_shift_low <<= 1;
+        self.attribute_shift_high <<= 1;
+    }
+
+    /// The palette and 2-bit color of the pixel being drawn, fine X scroll selects how far into the
+    /// shift registers it is
+    pub const fn pixel(&self, fine_x: u8) -> (u8, u8) {
+        const fn bit(register: u16, fine_x: u8) -> bool {
+            register & (0x8000 >> fine_x) != 0
+        }
+
+        let palette = util::combine_bools(
+            bit(self.attribute_shift_high, fine_x),
+            bit(self.attribute_shift_low, fine_x),
+        );
+        let color = util::combine_bools(
+            bit(self.pattern_shift_high, fine_x),
+            bit(self.pattern_shift_low, fine_x),
+        );
+        (palette, color)
+    }
+}
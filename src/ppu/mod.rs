@@ -1,19 +1,27 @@
+mod background;
 pub mod nametable;
+mod ntsc;
 mod object_attribute;
-mod palette;
+mod open_bus;
+pub mod palette;
 pub mod registers;
 pub mod renderer;
 
 use {
     self::{
-        nametable::{Nametable, NametableAddr, NAMETABLE_LEN},
-        object_attribute::{Object, ObjectAttributeMemory},
-        registers::Register,
-        renderer::{PixelBuffer, Renderer},
+        background::Background,
+        nametable::{NametableAddr, NAMETABLE_LEN},
+        object_attribute::{ObjectAttributeMemory, ScanlineSprite},
+        open_bus::OpenBus,
+        palette::SystemPalette,
+        registers::{Register, VramAddress},
+        renderer::{Frame, Renderer, BETWEEN_PLANES, TILE_LEN},
     },
     crate::{
         bus::{Clock, CycleCount},
         cartridge::MapperInstance,
+        region::Region,
+        savestate::snapshot_fields,
     },
     std::{
         ops::RangeInclusive,
@@ -21,59 +29,137 @@ use {
     },
 };
 
-pub type PixelReceiver = Receiver<Box<PixelBuffer>>;
-pub type PixelSender = Sender<Box<PixelBuffer>>;
+pub type PixelReceiver = Receiver<Frame>;
+pub type PixelSender = Sender<Frame>;
+
+pub enum VideoRequest {
+    /// Whether to draw at most eight sprites per scanline like the hardware, which causes flicker
+    SpriteLimit(bool),
+    /// The RGB values to output for every color
+    Palette(Box<SystemPalette>),
+    /// Whether to recreate the artifacts of composite video, which makes frames wider
+    NtscFilter(bool),
+}
 
 const VIDEO_RAM_SIZE: usize = NAMETABLE_LEN * 2;
 type VideoRam = [u8; VIDEO_RAM_SIZE];
 
 type ScanlineCount = u16;
+type DotCount = u16;
 
 /// https://www.nesdev.org/wiki/PPU
 pub struct Ppu {
     span: tracing::Span,
     pub renderer: Renderer,
     mapper: Option<MapperInstance>,
+    video_receiver: Option<Receiver<VideoRequest>>,
+    sprite_limit: bool,
 
     data_buffer: u8,
+    open_bus: OpenBus,
     vram: VideoRam,
     pub oam: ObjectAttributeMemory,
 
     control: registers::Control,
     mask: registers::Mask,
     pub status: registers::Status,
-    scroll: registers::Scroll,
-    address: registers::Address,
 
-    cycles: CycleCount,
+    /// The current VRAM address, which doubles as the scroll position while rendering ("v")
+    vram_address: VramAddress,
+    /// Where rendering starts from, written through PPUCTRL, PPUSCROLL and PPUADDR ("t")
+    temporary_address: VramAddress,
+    /// The horizontal scroll within a tile ("x")
+    fine_x: u8,
+    /// Whether the next write to PPUSCROLL or PPUADDR is the second one ("w")
+    write_toggle: bool,
+
+    background: Background,
+    /// Only the first eight are used unless the sprite limit is disabled
+    sprites: [ScanlineSprite; ObjectAttributeMemory::SPRITE_COUNT],
+    sprite_count: usize,
+
+    /// The next dot to be drawn
+    dot: DotCount,
     scanline: ScanlineCount,
+    odd_frame: bool,
     trigger_nmi: bool,
+    /// Set when PPUSTATUS is read just before vblank starts, which keeps the flag from being set
+    suppress_vblank: bool,
+    /// Set when vblank is reached, whether or not the flag was suppressed
+    frame_done: bool,
+
+    pub region: Region,
+    /// Leftover CPU cycles that did not add up to a whole dot yet, PAL runs 3.2 dots per cycle
+    partial_dots: usize,
 }
 
+snapshot_fields!(Ppu {
+    renderer.palette,
+    data_buffer,
+    open_bus,
+    vram,
+    oam,
+    control,
+    mask,
+    status,
+    vram_address,
+    temporary_address,
+    fine_x,
+    write_toggle,
+    background,
+    sprites,
+    sprite_count,
+    dot,
+    scanline,
+    odd_frame,
+    trigger_nmi,
+    suppress_vblank,
+    partial_dots,
+});
+
 impl Ppu {
     const PATTERN_TABLE_RANGE: RangeInclusive<u16> = 0..=0x1FFF;
     const NAMETABLE_RANGE: RangeInclusive<u16> = 0x2000..=0x3EFF;
     const PALETTE_RAM_RANGE: RangeInclusive<u16> = 0x3F00..=0x3FFF;
 
-    pub fn new(pixel_sender: Sender<Box<PixelBuffer>>) -> Self {
+    const DOTS_PER_SCANLINE: DotCount = 341;
+    const VISIBLE_SCANLINES: ScanlineCount = 240;
+
+    pub fn new(pixel_sender: PixelSender, video_receiver: Option<Receiver<VideoRequest>>) -> Self {
         Self {
             span: tracing::span!(tracing::Level::INFO, "ppu"),
             renderer: Renderer::new(pixel_sender),
             mapper: None,
+            video_receiver,
+            sprite_limit: true,
 
             data_buffer: 0,
+            open_bus: OpenBus::default(),
             vram: [0; VIDEO_RAM_SIZE],
             oam: ObjectAttributeMemory::default(),
 
             control: registers::Control::default(),
             mask: registers::Mask::default(),
             status: registers::Status::default(),
-            scroll: registers::Scroll::default(),
-            address: registers::Address::default(),
 
-            cycles: 0,
+            vram_address: VramAddress::default(),
+            temporary_address: VramAddress::default(),
+            fine_x: 0,
+            write_toggle: false,
+
+            background: Background::default(),
+            sprites: [ScanlineSprite::default(); ObjectAttributeMemory::SPRITE_COUNT],
+            sprite_count: 0,
+
+            dot: 0,
             scanline: 0,
+            odd_frame: false,
             trigger_nmi: false,
+            suppress_vblank: false,
+            frame_done: false,
+
+            region: Region::default(),
+            partial_dots: 0,
         }
     }
 
@@ -81,34 +167,70 @@ impl Ppu {
         self.renderer.reset();
 
         self.data_buffer = 0;
+        self.open_bus = OpenBus::default();
         self.vram = [0; VIDEO_RAM_SIZE];
         self.oam = ObjectAttributeMemory::default();
 
         self.control = registers::Control::default();
         self.mask = registers::Mask::default();
         self.status = registers::Status::default();
-        self.scroll = registers::Scroll::default();
-        self.address = registers::Address::default();
 
-        self.cycles = 0;
+        self.vram_address = VramAddress::default();
+        self.temporary_address = VramAddress::default();
+        self.fine_x = 0;
+        self.write_toggle = false;
+
+        self.background = Background::default();
+        self.sprite_count = 0;
+
+        self.dot = 0;
         self.scanline = 0;
+        self.odd_frame = false;
         self.trigger_nmi = false;
+        self.suppress_vblank = false;
+        self.frame_done = false;
+        self.partial_dots = 0;
+    }
+
+    /// The last scanline of the frame, on which the first tiles of the next one are fetched
+    const fn pre_render_scanline(&self) -> ScanlineCount {
+        self.region.scanlines() - 1
     }
 
     pub fn load_mapper(&mut self, mapper: MapperInstance) {
-        self.mapper = Some(mapper.clone());
-        self.renderer.load_mapper(mapper);
+        self.mapper = Some(mapper);
     }
 
     pub fn unload_mapper(&mut self) {
         self.mapper = None;
-        self.renderer.unload_mapper();
+        self.renderer.reset();
     }
 
     pub fn render(&mut self) {
         self.renderer.update()
     }
 
+    /// Apply the settings requested by the GUI
+    pub fn update_settings(&mut self) {
+        let Some(receiver) = &self.video_receiver else {
+            return;
+        };
+
+        while let Ok(request) = receiver.try_recv() {
+            match request {
+                VideoRequest::SpriteLimit(enabled) => {
+                    tracing::info!("sprite limit enabled: {enabled}");
+                    self.sprite_limit = enabled;
+                }
+                VideoRequest::Palette(colors) => self.renderer.set_colors(colors),
+                VideoRequest::NtscFilter(enabled) => {
+                    tracing::info!("NTSC filter enabled: {enabled}");
+                    self.renderer.set_ntsc_filter(enabled);
+                }
+            }
+        }
+    }
+
     fn to_nametable_index(&self, addr: u16) -> u16 {
         NametableAddr::mirror_vram_index(addr, self.mapper.as_ref().unwrap().borrow().mirroring())
     }
@@ -122,31 +244,45 @@ impl Ppu {
     #[tracing::instrument(skip(self), parent = &self.span)]
     fn increment_vram_address(&mut self) {
         let incr = self.control.vram_address_increment();
-        self.address.increment(incr);
+        self.vram_address.increment(incr);
         tracing::trace!(
             "address register incremented to ${:02X}",
-            self.address.value
+            self.vram_address.address()
         )
     }
 
     #[tracing::instrument(skip(self), parent = &self.span)]
     fn read_status(&mut self) -> u8 {
+        // Reading right around the start of vblank races with the flag being set
+        // https://www.nesdev.org/wiki/PPU_frame_timing#VBL_Flag_Timing
+        if self.scanline == self.region.vblank_scanline() {
+            match self.dot {
+                // A dot early the flag reads as clear, and isn't set for this frame
+                1 => self.suppress_vblank = true,
+                // On the same dot or the one after it reads as set, but there is no NMI
+                2 | 3 => self.trigger_nmi = false,
+                _ => {}
+            }
+        }
+
         let result = u8::from(self.status);
         tracing::trace!("status register: {:?}", self.status);
         self.status.set_vblank_started(false);
-        self.address.reset_latch();
-        self.scroll.reset_latch();
+        self.write_toggle = false;
         result
     }
 
     fn write_control(&mut self, data: u8) {
         let nmi_before = self.control.non_maskable_interrupt_at_vblank();
         self.control = registers::Control::from(data);
-        if !nmi_before
-            && self.control.non_maskable_interrupt_at_vblank()
-            && self.status.vblank_started()
-        {
+        self.temporary_address
+            .set_nametable(self.control.nametable_address());
+        let nmi_after = self.control.non_maskable_interrupt_at_vblank();
+        if !nmi_before && nmi_after && self.status.vblank_started() {
             self.trigger_nmi = true;
+        } else if !nmi_after && self.scanline == self.region.vblank_scanline() && self.dot <= 3 {
+            // Disabling it right as vblank starts cancels the NMI before the CPU notices
+            self.trigger_nmi = false;
         }
     }
 
@@ -154,35 +290,71 @@ impl Ppu {
         self.mask = registers::Mask::from(data);
     }
 
-    /// Helper for reading from PPUDATA
-    #[tracing::instrument(skip(self), parent = &self.span)]
-    fn read_data(&mut self) -> u8 {
-        let addr = self.address.value;
-        self.increment_vram_address();
+    /// https://www.nesdev.org/wiki/PPU_scrolling#$2005_first_write_(w_is_0)
+    fn write_scroll(&mut self, data: u8) {
+        if !self.write_toggle {
+            self.temporary_address.set_coarse_x(data >> 3);
+            self.fine_x = data & 0b0000_0111;
+        } else {
+            self.temporary_address.set_coarse_y(data >> 3);
+            self.temporary_address.set_fine_y(data & 0b0000_0111);
+        }
+        self.write_toggle = !self.write_toggle;
+    }
 
+    /// https://www.nesdev.org/wiki/PPU_scrolling#$2006_first_write_(w_is_0)
+    fn write_address(&mut self, data: u8) {
+        let temporary = u16::from(self.temporary_address);
+        self.temporary_address = if !self.write_toggle {
+            // The highest bit is cleared, the address is only 14 bits
+            VramAddress::from((temporary & 0x00FF) | (((data & 0b0011_1111) as u16) << 8))
+        } else {
+            let address = VramAddress::from((temporary & 0xFF00) | data as u16);
+            self.vram_address = address;
+            address
+        };
+        self.write_toggle = !self.write_toggle;
+    }
+
+    /// Read from the PPU's address space, as seen by rendering
+    fn read_memory(&mut self, addr: u16) -> u8 {
         if Self::PATTERN_TABLE_RANGE.contains(&addr) {
-            let result = self.mapper.as_ref().unwrap().borrow_mut().read_ppu(addr);
-            tracing::debug!("pattern table read at ${:04X}: ${:02X}", addr, result);
-            self.update_data_buffer(result)
+            self.mapper.as_ref().unwrap().borrow_mut().read_ppu(addr)
         } else if Self::NAMETABLE_RANGE.contains(&addr) {
-            let result = self.vram[self.to_nametable_index(addr) as usize];
-            tracing::debug!("nametable read at ${:04X}: ${:02X}", addr, result);
-            self.update_data_buffer(result)
-        } else if Self::PALETTE_RAM_RANGE.contains(&addr) {
-            // TODO: This should set the data buffer to the nametable "below" the pattern table
-            let result = self.renderer.palette[addr.into()];
+            self.vram[self.to_nametable_index(addr) as usize]
+        } else {
+            self.renderer.palette[addr.into()]
+        }
+    }
+
+    /// Helper for reading from PPUDATA, returns the result along with the bits it drives.
+    /// https://www.nesdev.org/wiki/PPU_registers#The_PPUDATA_read_buffer
+    #[tracing::instrument(skip(self), parent = &self.span)]
+    fn read_data(&mut self) -> (u8, u8) {
+        let addr = self.vram_address.address();
+        self.increment_vram_address();
+
+        if Self::PALETTE_RAM_RANGE.contains(&addr) {
+            // Palette reads skip the buffer, which is filled with the nametable underneath instead
+            self.data_buffer = self.read_memory(addr - 0x1000);
+            let mut result = self.renderer.palette[addr.into()] & 0x3F;
+            if self.mask.greyscale() {
+                result &= 0x30;
+            }
             tracing::debug!("palette RAM read at ${:04X}: ${:02X}", addr, result);
-            result
+            // The palette is only 6 bits wide, the top two come from the open bus
+            (result, 0b0011_1111)
         } else {
-            tracing::error!("invalid data read at ${:04X}", addr);
-            panic!()
+            let value = self.read_memory(addr);
+            tracing::debug!("buffered read at ${:04X}: ${:02X}", addr, value);
+            (self.update_data_buffer(value), 0xFF)
         }
     }
 
     /// Helper for writing with PPUDATA
     #[tracing::instrument(skip(self, data), parent = &self.span)]
     fn write_data(&mut self, data: u8) {
-        let addr = self.address.value;
+        let addr = self.vram_address.address();
 
         if Self::NAMETABLE_RANGE.contains(&addr) {
             let vram_index = self.to_nametable_index(addr) as usize;
@@ -207,29 +379,32 @@ impl Ppu {
 
     #[tracing::instrument(skip(self, register), parent = &self.span)]
     pub fn read_register(&mut self, register: &Register) -> u8 {
-        let result = match register {
-            Register::Status => self.read_status(),
-            Register::ObjectAttributeData => self.oam.read_data(),
+        let (data, driven) = match register {
+            // The low five bits of the status register aren't connected
+            Register::Status => (self.read_status(), 0b1110_0000),
+            Register::ObjectAttributeData => (self.oam.read_data(), 0xFF),
             Register::Data => self.read_data(),
-            _ => {
-                tracing::error!("unimplemented register {} read", register);
-                panic!()
-            }
+            // Write-only registers leave the whole latch as it was
+            _ => (0, 0),
         };
+        let result = self.open_bus.drive(data, driven);
         tracing::trace!("register {} read: ${:02X}", register, result);
         result
     }
 
     #[tracing::instrument(skip(self, register, data), parent = &self.span)]
     pub fn write_register(&mut self, register: &Register, data: u8) {
+        // Every write fills the latch, even to a read-only register
+        self.open_bus.drive(data, 0xFF);
         match register {
             Register::Control => self.write_control(data),
             Register::Mask => self.write_mask(data),
             Register::ObjectAttributeAddress => self.oam.write_address(data),
             Register::ObjectAttributeData => self.oam.write_data(data),
-            Register::Scroll => self.scroll.update(data),
-            Register::Address => self.address.update(data),
+            Register::Scroll => self.write_scroll(data),
+            Register::Address => self.write_address(data),
             Register::Data => self.write_data(data),
+            Register::Status => {}
             _ => {
                 tracing::error!("invalid register {} write of ${:02X}", register, data);
                 panic!()
@@ -238,6 +413,15 @@ impl Ppu {
         tracing::trace!("register {} write: ${:02X}", register, data);
     }
 
+    /// Whether a frame was finished since the last call
+    pub fn poll_frame(&mut self) -> bool {
+        std::mem::take(&mut self.frame_done)
+    }
+
+    pub const fn nmi_triggered(&self) -> bool {
+        self.trigger_nmi
+    }
+
     pub fn poll_nmi(&mut self) -> bool {
         let result = self.trigger_nmi;
         if result {
@@ -246,73 +430,263 @@ impl Ppu {
         result
     }
 
-    fn is_sprite_zero_hit(&self, cycle: usize) -> bool {
-        // TODO: This should check if a non-opaque BG pixel overlaps with a non-opaque sprite zero pixel,
-        // instead of triggering on any sprite zero pixel.
-        let obj = Object::from(&self.oam.memory[0..4]);
-        self.mask.show_sprites() && (obj.y + 5 == self.scanline as usize) && obj.x <= cycle
+    /// Set when an opaque pixel of sprite 0 overlaps an opaque background pixel, regardless of which one is drawn.
+    /// https://www.nesdev.org/wiki/PPU_OAM#Sprite_zero_hits
+    fn is_sprite_zero_hit(&self, x: u8, background_color: u8) -> bool {
+        let clipped =
+            x < 8 && !(self.mask.show_leftmost_background() && self.mask.show_leftmost_sprites());
+
+        // Both layers have to be enabled, and the hit can't happen on the last pixel of a line
+        self.mask.show_background()
+            && self.mask.show_sprites()
+            && background_color != 0
+            && x != 255
+            && !clipped
+            && self.sprites[..self.sprite_count]
+                .iter()
+                .any(|sprite| sprite.sprite_zero && sprite.pixel(x) != 0)
     }
-}
-
-impl Clock for Ppu {
-    const MULTIPLIER: usize = 3;
 
-    #[tracing::instrument(skip(self, cycles), parent = &self.span)]
-    fn tick_impl(&mut self, cycles: CycleCount) {
-        const CYCLES_PER_SCANLINE: CycleCount = 341;
-        const SCANLINES_PER_FRAME: ScanlineCount = 261;
-        const VBLANK_SCANLINE: ScanlineCount = 241;
+    fn rendering_enabled(&self) -> bool {
+        self.mask.show_background() || self.mask.show_sprites()
+    }
 
-        self.cycles += cycles;
+    /// Fetch the tiles of the background, one memory access every other dot.
+    /// https://www.nesdev.org/wiki/PPU_rendering#Cycles_1-256
+    fn fetch_background(&mut self) {
+        self.background.shift();
 
-        if self.cycles >= CYCLES_PER_SCANLINE {
-            if self.is_sprite_zero_hit(self.cycles) {
-                self.status.set_sprite_zero_hit(true);
+        match (self.dot - 1) % 8 {
+            0 => {
+                self.background.reload();
+                self.background.tile_index = self.read_memory(self.vram_address.tile_address());
+            }
+            2 => {
+                let attribute = self.read_memory(self.vram_address.attribute_address());
+                self.background.attribute =
+                    (attribute >> self.vram_address.attribute_shift()) & 0b11;
             }
+            4 => {
+                let address = self.background_pattern_address();
+                self.background.pattern_low = self.read_memory(address);
+            }
+            6 => {
+                let address = self.background_pattern_address() + BETWEEN_PLANES;
+                self.background.pattern_high = self.read_memory(address);
+            }
+            7 => self.vram_address.increment_coarse_x(),
+            _ => {}
+        }
+    }
 
-            self.cycles -= CYCLES_PER_SCANLINE;
-            self.scanline += 1;
+    fn background_pattern_address(&self) -> u16 {
+        let tile =
+            self.control.background_bank() + (self.background.tile_index as usize * TILE_LEN);
+        tile as u16 + self.vram_address.fine_y() as u16
+    }
+
+    /// Find the sprites on the next scanline and fetch their patterns
+    fn fetch_sprites(&mut self) {
+        let sprite_bank = self.control.sprite_bank();
+        let height = if sprite_bank.is_some() { 8 } else { 16 };
 
-            if self.scanline < VBLANK_SCANLINE && self.mask.show_background() {
-                let mirroring = self.mapper.as_ref().unwrap().borrow().mirroring();
-                let (first_nametable, second_nametable) = Nametable::from(
-                    &self.vram,
-                    NametableAddr::from(self.control.nametable_address() as u16),
-                    mirroring,
-                );
-                self.renderer.draw_scanline(
-                    self.scanline.into(),
-                    self.control.background_bank(),
-                    (&first_nametable, &second_nametable),
-                    (self.scroll.x, self.scroll.y),
-                );
+        let evaluation = self.oam.evaluate(self.scanline, height, self.sprite_limit);
+        self.sprite_count = evaluation.objects.len();
+        if evaluation.overflow {
+            self.status.set_sprite_overflow(true);
+        }
+
+        for (slot, (index, object)) in evaluation.objects.into_iter().enumerate() {
+            let row = self.scanline as usize - object.y;
+            let address = object.pattern_address(row, sprite_bank);
+            let (mut pattern_low, mut pattern_high) = (
+                self.read_memory(address),
+                self.read_memory(address + BETWEEN_PLANES),
+            );
+
+            if object.attrs.flip_horizontal() {
+                pattern_low = pattern_low.reverse_bits();
+                pattern_high = pattern_high.reverse_bits();
             }
 
-            if self.scanline == VBLANK_SCANLINE {
-                self.status.set_sprite_zero_hit(false);
-                self.status.set_vblank_started(true);
-                tracing::debug!("entering vblank, status: {:?}", self.status);
+            self.sprites[slot] = ScanlineSprite {
+                x: object.x as u8,
+                attrs: object.attrs,
+                pattern_low,
+                pattern_high,
+                sprite_zero: index == 0,
+            };
+        }
+    }
 
-                if self.control.non_maskable_interrupt_at_vblank() {
-                    self.trigger_nmi = true;
-                }
+    /// The memory accesses of the visible and pre-render scanlines
+    /// https://www.nesdev.org/wiki/PPU_rendering#Frame_timing_diagram
+    fn step_rendering(&mut self) {
+        let dot = self.dot;
+
+        if (2..=257).contains(&dot) || (321..=337).contains(&dot) {
+            self.fetch_background();
+        }
 
-                if self.mask.show_sprites() {
-                    self.renderer
-                        .draw_sprites(self.control.sprite_bank(), &self.oam);
+        match dot {
+            256 => self.vram_address.increment_y(),
+            257 => {
+                self.vram_address.copy_horizontal(self.temporary_address);
+                if self.scanline == self.pre_render_scanline() {
+                    // Nothing is evaluated for the first scanline
+                    self.sprite_count = 0;
+                } else {
+                    self.fetch_sprites();
                 }
+            }
+            260 | 324 if Some(dot) == self.scanline_clock_dot() => {
+                if let Some(mapper) = &self.mapper {
+                    mapper.borrow_mut().clock_scanline();
+                }
+            }
+            280..=304 if self.scanline == self.pre_render_scanline() => {
+                self.vram_address.copy_vertical(self.temporary_address);
+            }
+            _ => {}
+        }
+    }
+
+    /// The dot where address line A12 first rises on a scanline, which clocks the scanline counter
+    /// of mappers such as the MMC3. That is when the patterns start being fetched from the upper
+    /// pattern table: the sprites' at dot 260 and the background's at dot 324. 8x16 sprites can
+    /// come from either table and are treated as upper, as games using them with the MMC3 do.
+    /// https://www.nesdev.org/wiki/MMC3#IRQ_Specifics
+    fn scanline_clock_dot(&self) -> Option<DotCount> {
+        match (self.control.sprite_bank(), self.control.background_bank()) {
+            (Some(0), 0) => None,
+            (Some(0), _) => Some(324),
+            _ => Some(260),
+        }
+    }
+
+    /// Combine the background and sprites into the pixel at the current dot
+    fn output_pixel(&mut self) {
+        let x = (self.dot - 1) as u8;
+
+        // Either layer can be hidden in the leftmost 8 pixels of the screen
+        let show_background =
+            self.mask.show_background() && (x >= 8 || self.mask.show_leftmost_background());
+        let show_sprites =
+            self.mask.show_sprites() && (x >= 8 || self.mask.show_leftmost_sprites());
+
+        let (background_palette, background_color) = if show_background {
+            self.background.pixel(self.fine_x)
+        } else {
+            (0, 0)
+        };
+
+        let sprite = if show_sprites {
+            // Sprites earlier in OAM are drawn on top
+            self.sprites[..self.sprite_count]
+                .iter()
+                .find_map(|sprite| Some((sprite, sprite.pixel(x))).filter(|(_, color)| *color != 0))
+        } else {
+            None
+        };
+
+        if !self.status.sprite_zero_hit() && self.is_sprite_zero_hit(x, background_color) {
+            self.status.set_sprite_zero_hit(true);
+        }
 
-                tracing::info!("rendering frame");
+        // Only the first opaque sprite counts, so one behind the background still hides the sprites
+        // after it wherever the background is opaque.
+        // https://www.nesdev.org/wiki/PPU_sprite_priority
+        let palette_index = match sprite {
+            Some((sprite, color))
+                if !(sprite.attrs.behind_background() && background_color != 0) =>
+            {
+                0x10 | (sprite.attrs.palette() << 2) | color
             }
+            _ if background_color != 0 => (background_palette << 2) | background_color,
+            // The backdrop color
+            _ => 0,
+        };
 
-            if self.scanline > SCANLINES_PER_FRAME {
-                // Drawn every pixel, starting over
-                self.scanline = 0;
+        let color = self
+            .renderer
+            .palette
+            .color(palette_index as usize, self.mask);
+        self.renderer
+            .set_pixel(x as usize, self.scanline as usize, color);
+    }
+
+    fn step(&mut self) {
+        let visible = self.scanline < Self::VISIBLE_SCANLINES;
+
+        if self.rendering_enabled() && (visible || self.scanline == self.pre_render_scanline()) {
+            self.step_rendering();
+        }
+
+        if visible && (1..=256).contains(&self.dot) {
+            self.output_pixel();
+        }
+
+        if self.dot == 1 {
+            if self.scanline == self.region.vblank_scanline() {
+                self.frame_done = true;
+                self.open_bus.decay();
+
+                if !std::mem::take(&mut self.suppress_vblank) {
+                    self.status.set_vblank_started(true);
+                    tracing::debug!("entering vblank, status: {:?}", self.status);
+
+                    if self.control.non_maskable_interrupt_at_vblank() {
+                        self.trigger_nmi = true;
+                    }
+                }
+            } else if self.scanline == self.pre_render_scanline() {
                 self.trigger_nmi = false;
                 self.status.set_vblank_started(false);
                 self.status.set_sprite_zero_hit(false);
+                self.status.set_sprite_overflow(false);
                 tracing::debug!("finished computing frame");
             }
         }
+
+        self.dot += 1;
+
+        // The last dot of the pre-render scanline is skipped on odd frames while rendering, only on NTSC
+        // https://www.nesdev.org/wiki/PPU_frame_timing#Even/Odd_Frames
+        if self.odd_frame
+            && self.region == Region::Ntsc
+            && self.rendering_enabled()
+            && self.scanline == self.pre_render_scanline()
+            && self.dot == Self::DOTS_PER_SCANLINE - 1
+        {
+            self.dot = Self::DOTS_PER_SCANLINE;
+            self.renderer.skip_dot();
+        }
+
+        if self.dot == Self::DOTS_PER_SCANLINE {
+            self.dot = 0;
+            self.scanline += 1;
+            if self.scanline > self.pre_render_scanline() {
+                self.scanline = 0;
+                self.odd_frame = !self.odd_frame;
+            }
+        }
+    }
+}
+
+impl Clock for Ppu {
+    /// Convert CPU cycles to dots, which depends on the region
+    fn tick(&mut self, cycles: CycleCount) {
+        let (dots, per_cycles) = self.region.dots_per_cycles();
+        self.partial_dots += cycles * dots;
+        let whole_dots = self.partial_dots / per_cycles;
+        self.partial_dots %= per_cycles;
+        self.tick_impl(whole_dots);
+    }
+
+    #[tracing::instrument(skip(self, cycles), parent = &self.span)]
+    fn tick_impl(&mut self, cycles: CycleCount) {
+        for _ in 0..cycles {
+            self.step();
+        }
     }
 }
@@ -0,0 +1,121 @@
+//! A filter that recreates the look of the NTSC composite video signal.
+//!
+//! Every pixel is turned back into the square wave the PPU outputs, eight samples per pixel. Decoding that
+//! signal the way a TV does blurs colors into their neighbours, and because the phase of the color subcarrier
+//! shifts every scanline and every frame the artifacts crawl along edges.
+//! https://www.nesdev.org/wiki/NTSC_video
+
+use super::{
+    palette::{self, NTSC_PHASES, SYSTEM_PALETTE_LEN},
+    renderer::{Frame, HEIGHT, RGB_LEN, WIDTH},
+};
+
+/// Every 3 pixels are stretched to 7, which gets close to the aspect ratio of a TV
+pub const OUTPUT_WIDTH: usize = (WIDTH - 1) / 3 * 7 + 7;
+
+const SAMPLES_PER_PIXEL: usize = 8;
+const SAMPLES_PER_LINE: usize = WIDTH * SAMPLES_PER_PIXEL;
+
+/// A scanline is 341 dots long, so the subcarrier is 341 * 8 samples further along on the next one
+const PHASE_PER_LINE: usize = (341 * SAMPLES_PER_PIXEL) % NTSC_PHASES;
+/// Likewise for the 262 scanlines of a frame
+const PHASE_PER_FRAME: usize = (262 * PHASE_PER_LINE) % NTSC_PHASES;
+
+/// Luma is averaged over half a period of the subcarrier, leaving some of the chroma in it
+const LUMA_WINDOW: usize = NTSC_PHASES / 2;
+
+pub struct NtscFilter {
+    /// The signal of every color at every phase, computed once
+    signals: Box<[[f32; NTSC_PHASES]; SYSTEM_PALETTE_LEN]>,
+    carrier: [(f32, f32); NTSC_PHASES],
+    /// The phase the subcarrier starts the frame at
+    frame_phase: usize,
+    /// Running totals of the signal of a scanline, and of it multiplied with the carrier. Any window
+    /// of the signal can be summed with only two lookups this way.
+    sums: Vec<[f32; 3]>,
+}
+
+impl NtscFilter {
+    pub fn new() -> Self {
+        let mut signals = Box::new([[0.0; NTSC_PHASES]; SYSTEM_PALETTE_LEN]);
+        for (color, phases) in signals.iter_mut().enumerate() {
+            for (phase, signal) in phases.iter_mut().enumerate() {
+                *signal = palette::ntsc_signal(color, phase);
+            }
+        }
+
+        Self {
+            signals,
+            carrier: std::array::from_fn(|phase| palette::ntsc_carrier(phase, 0.0)),
+            frame_phase: 0,
+            sums: vec![[0.0; 3]; SAMPLES_PER_LINE + 1],
+        }
+    }
+
+    /// With the dot skipped on odd frames, the artifacts jitter between two positions instead of crawling
+    pub fn skip_dot(&mut self) {
+        self.frame_phase = (self.frame_phase + NTSC_PHASES - SAMPLES_PER_PIXEL) % NTSC_PHASES;
+    }
+
+    /// Turn a frame of system palette colors into RGB pixels
+    pub fn apply(&mut self, colors: &[u16]) -> Frame {
+        let mut pixels = Vec::with_capacity(OUTPUT_WIDTH * HEIGHT * RGB_LEN);
+
+        for (row, colors) in colors.chunks_exact(WIDTH).enumerate() {
+            let line_phase = (self.frame_phase + row * PHASE_PER_LINE) % NTSC_PHASES;
+
+            let mut total = [0.0; 3];
+            for sample in 0..SAMPLES_PER_LINE {
+                let phase = (line_phase + sample) % NTSC_PHASES;
+                let signal = self.signals[colors[sample / SAMPLES_PER_PIXEL] as usize][phase];
+                let (cos, sin) = self.carrier[phase];
+
+                total[0] += signal;
+                total[1] += signal * cos;
+                total[2] += signal * sin;
+                self.sums[sample + 1] = total;
+            }
+
+            for column in 0..OUTPUT_WIDTH {
+                let center = column * SAMPLES_PER_LINE / OUTPUT_WIDTH;
+                // Past the edges of the screen the signal is black
+                let window = |len: usize, component: usize| {
+                    let start = center.saturating_sub(len / 2);
+                    let end = (center + len / 2).min(SAMPLES_PER_LINE);
+                    (self.sums[end][component] - self.sums[start][component]) / len as f32
+                };
+
+                let (red, green, blue) = palette::yiq_to_rgb(
+                    window(LUMA_WINDOW, 0),
+                    window(NTSC_PHASES, 1),
+                    window(NTSC_PHASES, 2),
+                );
+                pixels.extend_from_slice(&[red, green, blue]);
+            }
+        }
+
+        self.frame_phase = (self.frame_phase + PHASE_PER_FRAME) % NTSC_PHASES;
+        Frame {
+            width: OUTPUT_WIDTH,
+            height: HEIGHT,
+            pixels,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flat_colors() {
+        let mut filter = NtscFilter::new();
+        let frame = filter.apply(&[0x30; WIDTH * HEIGHT]);
+        assert_eq!((frame.width, frame.height), (602, HEIGHT));
+        assert_eq!(frame.pixels.len(), 602 * HEIGHT * RGB_LEN);
+
+        // Away from the edges a grey without chroma comes out as itself
+        let middle = (HEIGHT / 2 * OUTPUT_WIDTH + OUTPUT_WIDTH / 2) * RGB_LEN;
+        assert_eq!(&frame.pixels[middle..middle + RGB_LEN], &[255, 255, 255]);
+    }
+}
@@ -1,5 +1,8 @@
-use super::renderer::PIXELS_PER_TILE;
-use crate::util;
+use super::renderer::{PIXELS_PER_TILE, TILE_LEN};
+use crate::{
+    savestate::{snapshot_bitfield, snapshot_fields},
+    util,
+};
 use std::ops::{Index, Range};
 use tartan_bitfield::bitfield;
 
@@ -10,8 +13,14 @@ pub struct ObjectAttributeMemory {
     address: u8,
 }
 
+snapshot_fields!(ObjectAttributeMemory { memory, address });
+
 impl ObjectAttributeMemory {
     pub const MEMORY_SIZE: usize = 0x100;
+    pub const SPRITE_COUNT: usize = Self::MEMORY_SIZE / 4;
+    pub const SPRITES_PER_SCANLINE: usize = 8;
+    /// Bits 2-4 of the attribute byte don't exist, see `ObjectAttributes`
+    const ATTRIBUTE_MASK: u8 = 0xE3;
 
     #[tracing::instrument(skip(self, data), parent = &self.span)]
     pub fn write_address(&mut self, data: u8) {
@@ -22,6 +31,11 @@ impl ObjectAttributeMemory {
     #[tracing::instrument(skip(self, data), parent = &self.span)]
     pub fn write_data(&mut self, data: u8) {
         tracing::trace!("oam write at ${:02X}: ${:02X}", self.address, data);
+        let data = if self.address % 4 == 2 {
+            data & Self::ATTRIBUTE_MASK
+        } else {
+            data
+        };
         self.memory[self.address as usize] = data;
         self.address = self.address.wrapping_add(1);
     }
@@ -53,6 +67,47 @@ impl ObjectAttributeMemory {
             oam: self,
         }
     }
+
+    /// The sprites that appear on the scanline after the given one, in order of priority.
+    /// Sprites are drawn one line lower than their Y coordinate, so this is done a line in advance.
+    /// https://www.nesdev.org/wiki/PPU_sprite_evaluation
+    pub fn evaluate(&self, scanline: u16, height: u8, sprite_limit: bool) -> Evaluation {
+        let in_range = |y: u8| (scanline as usize).wrapping_sub(y as usize) < height as usize;
+
+        let mut evaluation = Evaluation::default();
+        // The byte of the sprite that is compared against the scanline once eight have been found
+        let mut byte = 0;
+
+        for (index, object) in self.iter().enumerate() {
+            let found = evaluation.objects.len();
+
+            // The hardware keeps looking for a ninth sprite, but increments the byte index along
+            // with the sprite index, so it compares tile indices, attributes and X coordinates as
+            // if they were Y coordinates.
+            // https://www.nesdev.org/wiki/PPU_sprite_evaluation#Sprite_overflow_bug
+            if found >= Self::SPRITES_PER_SCANLINE && !evaluation.overflow {
+                if in_range(self.memory[index * 4 + byte]) {
+                    evaluation.overflow = true;
+                } else {
+                    byte = (byte + 1) % 4;
+                }
+            }
+
+            if in_range(object.y as u8) && (found < Self::SPRITES_PER_SCANLINE || !sprite_limit) {
+                evaluation.objects.push((index, object));
+            }
+        }
+        evaluation
+    }
+}
+
+/// The outcome of evaluating the sprites of a scanline
+#[derive(Default)]
+pub struct Evaluation {
+    /// The sprites on the scanline with their OAM index, all of them when the limit is disabled
+    pub objects: Vec<(usize, Object)>,
+    /// The value of the sprite overflow flag, which has both false positives and negatives
+    pub overflow: bool,
 }
 
 impl Default for ObjectAttributeMemory {
@@ -92,6 +147,8 @@ bitfield! {
     }
 }
 
+snapshot_bitfield!(ObjectAttributes: u8);
+
 pub struct Object {
     pub x: usize,
     pub y: usize,
@@ -123,22 +180,28 @@ impl Object {
         self.tile_index & 0b1111_1110
     }
 
-    pub fn pixel_position(&self, x: usize, y: usize) -> (usize, usize) {
-        const LEN: usize = PIXELS_PER_TILE - 1;
-
-        let x = if self.attrs.flip_horizontal() {
-            (self.x + LEN) - x
-        } else {
-            self.x + x
+    /// The address of the low plane of a row of the sprite, `sprite_bank` is `None` for 8x16 sprites
+    pub fn pattern_address(&self, mut row: usize, sprite_bank: Option<usize>) -> u16 {
+        let (bank, mut tile_index, height) = match sprite_bank {
+            Some(bank) => (bank, self.tile_index, PIXELS_PER_TILE),
+            None => (
+                self.bank_8x16(),
+                self.tile_index_8x16(),
+                PIXELS_PER_TILE * 2,
+            ),
         };
 
-        let y = if self.attrs.flip_vertical() {
-            (self.y + LEN) - y
-        } else {
-            self.y + y
-        };
+        if self.attrs.flip_vertical() {
+            row = height - 1 - row;
+        }
 
-        (x, y)
+        // 8x16 sprites are made of two tiles stacked on top of each other
+        if row >= PIXELS_PER_TILE {
+            tile_index += 1;
+            row -= PIXELS_PER_TILE;
+        }
+
+        (bank + (tile_index * TILE_LEN) + row) as u16
     }
 }
 
@@ -158,3 +221,85 @@ impl<'a> Iterator for OamIterator<'a> {
         Some(Object::from(&self.oam.memory[self.index - 4..self.index]))
     }
 }
+
+/// A sprite on the scanline being drawn, with its row of pixels already fetched
+#[derive(Default, Clone, Copy)]
+pub struct ScanlineSprite {
+    pub x: u8,
+    pub attrs: ObjectAttributes,
+    /// Already flipped when the sprite is mirrored horizontally
+    pub pattern_low: u8,
+    pub pattern_high: u8,
+    pub sprite_zero: bool,
+}
+
+snapshot_fields!(ScanlineSprite {
+    x,
+    attrs,
+    pattern_low,
+    pattern_high,
+    sprite_zero,
+});
+
+impl ScanlineSprite {
+    /// The 2-bit color of the sprite at the given X coordinate, zero when transparent or not covered
+    pub fn pixel(&self, x: u8) -> u8 {
+        match x.checked_sub(self.x) {
+            Some(offset @ 0..=7) => util::combine_bools(
+                util::nth_bit(self.pattern_high, 7 - offset),
+                util::nth_bit(self.pattern_low, 7 - offset),
+            ),
+            _ => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn oam_with(sprites: &[[u8; 4]]) -> ObjectAttributeMemory {
+        let mut oam = ObjectAttributeMemory::default();
+        // Move every sprite off screen first
+        oam.memory.fill(0xFF);
+        for (index, sprite) in sprites.iter().enumerate() {
+            oam.memory[index * 4..index * 4 + 4].copy_from_slice(sprite);
+        }
+        oam
+    }
+
+    #[test]
+    fn sprite_limit_and_overflow() {
+        let oam = oam_with(&[[10, 0, 0, 0]; 9]);
+        let evaluation = oam.evaluate(10, 8, true);
+        assert_eq!(evaluation.objects.len(), 8);
+        assert!(evaluation.overflow);
+
+        let evaluation = oam.evaluate(10, 8, false);
+        assert_eq!(evaluation.objects.len(), 9);
+        assert_eq!(evaluation.objects[8].0, 8);
+    }
+
+    #[test]
+    fn overflow_bug() {
+        // The ninth sprite is out of range, so the tile index of the tenth is compared instead
+        let mut sprites = [[10, 0, 0, 0]; 10];
+        sprites[8] = [100, 0, 0, 0];
+        sprites[9] = [100, 10, 0, 0];
+        assert!(oam_with(&sprites).evaluate(10, 8, true).overflow);
+
+        // And a tenth sprite that is in range is missed
+        sprites[9] = [10, 100, 0, 0];
+        assert!(!oam_with(&sprites).evaluate(10, 8, true).overflow);
+    }
+
+    #[test]
+    fn unimplemented_attribute_bits() {
+        let mut oam = ObjectAttributeMemory::default();
+        (0..4).for_each(|_| oam.write_data(0xFF));
+        assert_eq!(oam.memory[..4], [0xFF, 0xFF, 0xE3, 0xFF]);
+
+        oam.write_address(2);
+        assert_eq!(oam.read_data(), 0xE3);
+    }
+}
@@ -0,0 +1,58 @@
+use crate::{savestate::snapshot_fields, util};
+
+/// The I/O data latch between the CPU and the PPU. Reading a write-only register, or the bits of a
+/// register that aren't driven, returns what was last on it. Bits that aren't refreshed decay to 0.
+/// https://www.nesdev.org/wiki/Open_bus_behavior#PPU_open_bus
+#[derive(Default)]
+pub struct OpenBus {
+    value: u8,
+    /// Frames left before each bit decays
+    decay: [u8; 8],
+}
+
+snapshot_fields!(OpenBus { value, decay });
+
+impl OpenBus {
+    /// A bit holds its value for roughly 600 ms
+    const DECAY_FRAMES: u8 = 36;
+
+    /// Put the bits of `data` selected by `driven` on the latch, and return the whole latch
+    pub fn drive(&mut self, data: u8, driven: u8) -> u8 {
+        self.value = (self.value & !driven) | (data & driven);
+        for (bit, frames) in self.decay.iter_mut().enumerate() {
+            if util::nth_bit(driven, bit as u8) {
+                *frames = Self::DECAY_FRAMES;
+            }
+        }
+        self.value
+    }
+
+    /// Called once per frame
+    pub fn decay(&mut self) {
+        for (bit, frames) in self.decay.iter_mut().enumerate() {
+            if *frames > 0 {
+                *frames -= 1;
+                if *frames == 0 {
+                    self.value &= !(1 << bit);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decay() {
+        let mut open_bus = OpenBus::default();
+        assert_eq!(open_bus.drive(0xFF, 0xFF), 0xFF);
+        (0..10).for_each(|_| open_bus.decay());
+
+        // Only the driven bits are refreshed
+        assert_eq!(open_bus.drive(0x00, 0b1110_0000), 0b0001_1111);
+        (10..OpenBus::DECAY_FRAMES).for_each(|_| open_bus.decay());
+        assert_eq!(open_bus.value, 0);
+    }
+}
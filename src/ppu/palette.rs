@@ -1,41 +1,39 @@
-use std::ops::{Index, IndexMut};
+use super::registers::Mask;
+use crate::savestate::snapshot_fields;
+use std::{
+    fmt, fs,
+    ops::{Index, IndexMut},
+    path::PathBuf,
+    str::FromStr,
+};
 
 pub type Color = (u8, u8, u8);
 
+pub const COLOR_COUNT: usize = 64;
+/// Every combination of the three emphasis bits has its own set of colors
+pub const SYSTEM_PALETTE_LEN: usize = COLOR_COUNT * 8;
+pub type SystemPalette = [Color; SYSTEM_PALETTE_LEN];
+
 pub const PALETTE_TABLE_LEN: usize = 32;
 type PaletteData = [u8; PALETTE_TABLE_LEN];
 
-const PALETTE_ENTRY_LEN: usize = 4;
-pub type PaletteEntry = [u8; PALETTE_ENTRY_LEN];
-
 pub struct Palette {
     data: PaletteData,
 }
 
-impl Palette {
-    pub fn background_entry(&self, index: usize) -> PaletteEntry {
-        let palette_start = (index * PALETTE_ENTRY_LEN) + 1;
-        [
-            self[0], // Background color
-            self[palette_start],
-            self[palette_start + 1],
-            self[palette_start + 2],
-        ]
-    }
-
-    pub fn sprite_entry(&self, index: usize) -> PaletteEntry {
-        // The palette table starts at 0x3F00 but the sprite entries do at 0x3F11
-        let start = 0x11 + (index * PALETTE_ENTRY_LEN);
-        [
-            0, // Transparant
-            self[start],
-            self[start + 1],
-            self[start + 2],
-        ]
-    }
+snapshot_fields!(Palette { data });
 
-    pub const fn get(palette: PaletteEntry, index: usize) -> Color {
-        PALETTE_TABLE[palette[index] as usize]
+impl Palette {
+    /// The index into the system palette of the color at the given index of palette RAM, with the
+    /// emphasis bits above the 6-bit color.
+    /// https://www.nesdev.org/wiki/PPU_registers#Color_effects
+    pub fn color(&self, index: usize, mask: Mask) -> u16 {
+        let mut color = self[index] & 0x3F;
+        if mask.greyscale() {
+            // Only the grey column of each row is left
+            color &= 0x30;
+        }
+        ((mask.emphasis() as u16) << 6) | color as u16
     }
 
     const fn mirror(mut addr: usize) -> usize {
@@ -70,7 +68,196 @@ impl IndexMut<usize> for Palette {
     }
 }
 
-pub const PALETTE_TABLE: [Color; 64] = [
+/// Expand 64 colors to the full system palette. Every emphasis bit that is set darkens the other
+/// two components, except in the black columns $xE and $xF.
+/// https://www.nesdev.org/wiki/NTSC_video#Color_Tint_Bits
+pub fn with_emphasis(colors: &[Color; COLOR_COUNT]) -> Box<SystemPalette> {
+    const ATTENUATION: f32 = 0.816_328;
+
+    let mut palette = Box::new([(0, 0, 0); SYSTEM_PALETTE_LEN]);
+    for (index, entry) in palette.iter_mut().enumerate() {
+        let (emphasis, color) = (index / COLOR_COUNT, index % COLOR_COUNT);
+        let (mut red, mut green, mut blue) = colors[color];
+
+        if color & 0x0E != 0x0E {
+            let darken = |component: u8, own_bit: usize| {
+                let others = (emphasis & !(1 << own_bit)).count_ones() as i32;
+                (component as f32 * ATTENUATION.powi(others)).round() as u8
+            };
+            (red, green, blue) = (darken(red, 0), darken(green, 1), darken(blue, 2));
+        }
+        *entry = (red, green, blue);
+    }
+    palette
+}
+
+/// Parse a `.pal` file, which holds RGB triplets for either the 64 colors or all of the system palette
+pub fn from_pal(data: &[u8]) -> Result<Box<SystemPalette>, String> {
+    let colors: Vec<Color> = data
+        .chunks_exact(3)
+        .map(|rgb| (rgb[0], rgb[1], rgb[2]))
+        .collect();
+
+    match data.len() {
+        len if len == COLOR_COUNT * 3 => Ok(with_emphasis(colors[..].try_into().unwrap())),
+        len if len == SYSTEM_PALETTE_LEN * 3 => {
+            let mut palette = Box::new([(0, 0, 0); SYSTEM_PALETTE_LEN]);
+            palette.copy_from_slice(&colors);
+            Ok(palette)
+        }
+        len => Err(format!(
+            "palette is {len} bytes, expected {} or {}",
+            COLOR_COUNT * 3,
+            SYSTEM_PALETTE_LEN * 3
+        )),
+    }
+}
+
+/// The color subcarrier of the NTSC signal repeats every 12 samples
+pub const NTSC_PHASES: usize = 12;
+
+/// The NTSC signal the PPU outputs for a color of the system palette at a phase of the color
+/// subcarrier, scaled so that black is 0 and white is 1.
+/// https://www.nesdev.org/wiki/NTSC_video#Brightness_Levels
+pub fn ntsc_signal(color: usize, phase: usize) -> f32 {
+    // Voltages of the signal, relative to sync
+    const BLACK: f32 = 0.518;
+    const WHITE: f32 = 1.962;
+    const ATTENUATION: f32 = 0.746;
+    const LOW: [f32; 4] = [0.350, 0.518, 0.962, 1.550];
+    const HIGH: [f32; 4] = [1.094, 1.506, 1.962, 1.962];
+
+    let (emphasis, color) = (color / COLOR_COUNT, color % COLOR_COUNT);
+    let (hue, mut level) = (color & 0x0F, color >> 4);
+    // The black columns are always at the second level
+    if hue > 13 {
+        level = 1;
+    }
+
+    let (mut low, mut high) = (LOW[level], HIGH[level]);
+    if hue == 0 {
+        low = high;
+    } else if hue > 12 {
+        high = low;
+    }
+
+    // The color is a square wave, high for half of the phases
+    let in_phase = |hue: usize| (hue + phase) % NTSC_PHASES < NTSC_PHASES / 2;
+    let mut signal = if in_phase(hue) { high } else { low };
+    if (0..3).any(|bit| emphasis & (1 << bit) != 0 && in_phase(bit * 4)) {
+        signal *= ATTENUATION;
+    }
+    (signal - BLACK) / (WHITE - BLACK)
+}
+
+/// The cosine and sine to demodulate the chroma at a phase of the color subcarrier with, with the
+/// hue rotated by the given amount of degrees
+pub fn ntsc_carrier(phase: usize, hue: f32) -> (f32, f32) {
+    /// Lines up the phases so that color $x1 is blue, $x6 red and $xA green at a hue of zero
+    const HUE_OFFSET: f32 = 105.0;
+
+    let angle = (phase as f32 * 30.0 + HUE_OFFSET + hue).to_radians();
+    (angle.cos(), angle.sin())
+}
+
+/// https://en.wikipedia.org/wiki/YIQ#From_YIQ_to_RGB
+pub fn yiq_to_rgb(y: f32, i: f32, q: f32) -> Color {
+    let to_byte = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+    (
+        to_byte(y + 0.946_882 * i + 0.623_557 * q),
+        to_byte(y - 0.274_788 * i - 0.635_691 * q),
+        to_byte(y - 1.108_545 * i + 1.709_007 * q),
+    )
+}
+
+/// Generate the system palette by decoding a model of the NTSC signal the PPU outputs. The hue is
+/// rotated by the given amount of degrees, and the saturation scales the chroma.
+/// https://www.nesdev.org/wiki/NTSC_video
+pub fn generate_ntsc(hue: f32, saturation: f32) -> Box<SystemPalette> {
+    let mut palette = Box::new([(0, 0, 0); SYSTEM_PALETTE_LEN]);
+    for (color, entry) in palette.iter_mut().enumerate() {
+        let (mut y, mut i, mut q) = (0.0, 0.0, 0.0);
+        for phase in 0..NTSC_PHASES {
+            let signal = ntsc_signal(color, phase) / NTSC_PHASES as f32;
+            let (cos, sin) = ntsc_carrier(phase, hue);
+            y += signal;
+            i += signal * cos * saturation;
+            q += signal * sin * saturation;
+        }
+        *entry = yiq_to_rgb(y, i, q);
+    }
+    palette
+}
+
+/// Where the colors of the system palette come from
+#[derive(Clone, Default, PartialEq)]
+pub enum PaletteChoice {
+    /// The colors of [`PALETTE_TABLE`]
+    #[default]
+    BuiltIn,
+    /// The colors of [`PALETTE_TABLE_2C02`]
+    Measured2C02,
+    /// See [`generate_ntsc`]
+    Ntsc { hue: f32, saturation: f32 },
+    /// A `.pal` file, see [`from_pal`]
+    File(PathBuf),
+}
+
+impl PaletteChoice {
+    pub const NTSC_DEFAULT: Self = Self::Ntsc {
+        hue: 0.0,
+        saturation: 1.0,
+    };
+
+    pub fn colors(&self) -> Result<Box<SystemPalette>, String> {
+        match self {
+            Self::BuiltIn => Ok(with_emphasis(&PALETTE_TABLE)),
+            Self::Measured2C02 => Ok(with_emphasis(&PALETTE_TABLE_2C02)),
+            Self::Ntsc { hue, saturation } => Ok(generate_ntsc(*hue, *saturation)),
+            Self::File(path) => {
+                let data = fs::read(path)
+                    .map_err(|err| format!("failed to read {}: {err}", path.display()))?;
+                from_pal(&data)
+            }
+        }
+    }
+}
+
+/// The format used to remember the choice between sessions
+impl fmt::Display for PaletteChoice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BuiltIn => write!(f, "built-in"),
+            Self::Measured2C02 => write!(f, "2c02"),
+            Self::Ntsc { hue, saturation } => write!(f, "ntsc:{hue}:{saturation}"),
+            Self::File(path) => write!(f, "file:{}", path.display()),
+        }
+    }
+}
+
+impl FromStr for PaletteChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "built-in" => Ok(Self::BuiltIn),
+            None if s == "2c02" => Ok(Self::Measured2C02),
+            Some(("file", path)) => Ok(Self::File(PathBuf::from(path))),
+            Some(("ntsc", parameters)) => {
+                let (hue, saturation) = parameters
+                    .split_once(':')
+                    .and_then(|(hue, saturation)| {
+                        Some((hue.parse().ok()?, saturation.parse().ok()?))
+                    })
+                    .ok_or_else(|| format!("invalid NTSC palette parameters '{parameters}'"))?;
+                Ok(Self::Ntsc { hue, saturation })
+            }
+            _ => Err(format!("unknown palette '{s}'")),
+        }
+    }
+}
+
+pub const PALETTE_TABLE: [Color; COLOR_COUNT] = [
     (0x80, 0x80, 0x80),
     (0x00, 0x3D, 0xA6),
     (0x00, 0x12, 0xB0),
@@ -136,3 +323,121 @@ pub const PALETTE_TABLE: [Color; 64] = [
     (0x11, 0x11, 0x11),
     (0x11, 0x11, 0x11),
 ];
+
+/// Colors measured from the composite output of a 2C02
+/// https://www.nesdev.org/wiki/PPU_palettes#2C02
+pub const PALETTE_TABLE_2C02: [Color; COLOR_COUNT] = [
+    (0x62, 0x62, 0x62),
+    (0x00, 0x1F, 0xB2),
+    (0x24, 0x04, 0xC8),
+    (0x52, 0x00, 0xB2),
+    (0x73, 0x00, 0x76),
+    (0x80, 0x00, 0x24),
+    (0x73, 0x0B, 0x00),
+    (0x52, 0x28, 0x00),
+    (0x24, 0x44, 0x00),
+    (0x00, 0x57, 0x00),
+    (0x00, 0x5C, 0x00),
+    (0x00, 0x53, 0x24),
+    (0x00, 0x3C, 0x76),
+    (0x00, 0x00, 0x00),
+    (0x00, 0x00, 0x00),
+    (0x00, 0x00, 0x00),
+    (0xAB, 0xAB, 0xAB),
+    (0x0D, 0x57, 0xFF),
+    (0x4B, 0x30, 0xFF),
+    (0x8A, 0x13, 0xFF),
+    (0xBC, 0x08, 0xD6),
+    (0xD2, 0x12, 0x69),
+    (0xC7, 0x2E, 0x00),
+    (0x9D, 0x54, 0x00),
+    (0x60, 0x7B, 0x00),
+    (0x20, 0x98, 0x00),
+    (0x00, 0xA3, 0x00),
+    (0x00, 0x99, 0x42),
+    (0x00, 0x7D, 0xB4),
+    (0x00, 0x00, 0x00),
+    (0x00, 0x00, 0x00),
+    (0x00, 0x00, 0x00),
+    (0xFF, 0xFF, 0xFF),
+    (0x53, 0xAE, 0xFF),
+    (0x90, 0x85, 0xFF),
+    (0xD3, 0x65, 0xFF),
+    (0xFF, 0x57, 0xFF),
+    (0xFF, 0x5D, 0xCF),
+    (0xFF, 0x77, 0x57),
+    (0xFA, 0x9E, 0x00),
+    (0xBD, 0xC7, 0x00),
+    (0x7A, 0xE7, 0x00),
+    (0x43, 0xF6, 0x11),
+    (0x26, 0xEF, 0x7E),
+    (0x2C, 0xD5, 0xF6),
+    (0x4E, 0x4E, 0x4E),
+    (0x00, 0x00, 0x00),
+    (0x00, 0x00, 0x00),
+    (0xFF, 0xFF, 0xFF),
+    (0xB6, 0xE1, 0xFF),
+    (0xCE, 0xD1, 0xFF),
+    (0xE9, 0xC3, 0xFF),
+    (0xFF, 0xBC, 0xFF),
+    (0xFF, 0xBD, 0xF4),
+    (0xFF, 0xC6, 0xC3),
+    (0xFF, 0xD5, 0x9A),
+    (0xE9, 0xE6, 0x81),
+    (0xCE, 0xF4, 0x81),
+    (0xB6, 0xFB, 0x9A),
+    (0xA9, 0xFA, 0xC3),
+    (0xA9, 0xF0, 0xF4),
+    (0xB8, 0xB8, 0xB8),
+    (0x00, 0x00, 0x00),
+    (0x00, 0x00, 0x00),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn greyscale_and_emphasis() {
+        let mut palette = Palette::default();
+        palette[0] = 0x2A;
+
+        let mask = Mask::from(0b1010_0001);
+        assert_eq!(palette.color(0, mask), (0b101 << 6) | 0x20);
+
+        let system = with_emphasis(&PALETTE_TABLE);
+        assert_eq!(system[0x20], PALETTE_TABLE[0x20]);
+        // Red emphasis keeps red and darkens green and blue
+        let (red, green, blue) = system[(0b001 << 6) | 0x20];
+        assert_eq!(red, 0xFF);
+        assert!(green < 0xFF && blue < 0xFF);
+        // The black columns are left alone
+        assert_eq!(system[(0b111 << 6) | 0x0F], PALETTE_TABLE[0x0F]);
+    }
+
+    #[test]
+    fn pal_files() {
+        let short: Vec<u8> = PALETTE_TABLE
+            .iter()
+            .flat_map(|&(red, green, blue)| [red, green, blue])
+            .collect();
+        assert_eq!(from_pal(&short).unwrap(), with_emphasis(&PALETTE_TABLE));
+
+        let full: Vec<u8> = (0..SYSTEM_PALETTE_LEN * 3).map(|i| i as u8).collect();
+        assert_eq!(from_pal(&full).unwrap()[1], (3, 4, 5));
+
+        assert!(from_pal(&short[1..]).is_err());
+    }
+
+    #[test]
+    fn choice_setting() {
+        for choice in [
+            PaletteChoice::BuiltIn,
+            PaletteChoice::Measured2C02,
+            PaletteChoice::NTSC_DEFAULT,
+            PaletteChoice::File(PathBuf::from("/tmp/colors.pal")),
+        ] {
+            assert!(choice.to_string().parse::<PaletteChoice>().unwrap() == choice);
+        }
+    }
+}
@@ -11,5 +11,6 @@ bitfield! {
         [5] pub emphasize_red,
         [6] pub emphasize_green,
         [7] pub emphasize_blue,
+        [5..=7] pub emphasis: u8,
     }
 }
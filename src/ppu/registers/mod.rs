@@ -1,39 +1,17 @@
-mod address;
 mod control;
 mod mask;
-mod scroll;
 mod status;
+mod vram_address;
 
-pub use address::Address;
 pub use control::Control;
 pub use mask::Mask;
-pub use scroll::Scroll;
 pub use status::Status;
+pub use vram_address::VramAddress;
 
+use crate::savestate::snapshot_bitfield;
 use std::ops::RangeInclusive;
 
-#[derive(Debug)]
-pub enum Mutability {
-    Read,
-    Write,
-    ReadWrite,
-}
-
-impl Mutability {
-    pub fn readable(&self) -> bool {
-        match self {
-            Self::ReadWrite | Self::Read => true,
-            Self::Write => false,
-        }
-    }
-
-    pub fn writable(&self) -> bool {
-        match self {
-            Self::ReadWrite | Self::Write => true,
-            Self::Read => false,
-        }
-    }
-}
+snapshot_bitfield!(Control: u8, Mask: u8, Status: u8, VramAddress: u16);
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum Register {
@@ -48,26 +26,25 @@ pub enum Register {
     ObjectAttributeDirectMemoryAccess,
 }
 
-const REGISTERS: [(u16, Register, Mutability); 8] = [
-    (0, Register::Control, Mutability::Write),
-    (1, Register::Mask, Mutability::Write),
-    (2, Register::Status, Mutability::Read),
-    (3, Register::ObjectAttributeAddress, Mutability::Write),
-    (4, Register::ObjectAttributeData, Mutability::ReadWrite),
-    (5, Register::Scroll, Mutability::Write),
-    (6, Register::Address, Mutability::Write),
-    (7, Register::Data, Mutability::ReadWrite),
+/// Only status, OAM data and data can be read, reading the others returns the open bus.
+/// Writing to status only fills the open bus.
+const REGISTERS: [(u16, Register); 8] = [
+    (0, Register::Control),
+    (1, Register::Mask),
+    (2, Register::Status),
+    (3, Register::ObjectAttributeAddress),
+    (4, Register::ObjectAttributeData),
+    (5, Register::Scroll),
+    (6, Register::Address),
+    (7, Register::Data),
 ];
 
-pub fn get_register(address: u16) -> Option<(&'static Register, &'static Mutability)> {
+pub fn get_register(address: u16) -> Option<&'static Register> {
     const REGISTERS_RANGE: RangeInclusive<u16> = 0x2000..=0x3FFF;
     if !REGISTERS_RANGE.contains(&address) {
         // TODO: Remove this when I/O registers are properly implemented
         if address == 0x4014 {
-            return Some((
-                &Register::ObjectAttributeDirectMemoryAccess,
-                &Mutability::Write,
-            ));
+            return Some(&Register::ObjectAttributeDirectMemoryAccess);
         } else {
             return None;
         }
@@ -75,13 +52,9 @@ pub fn get_register(address: u16) -> Option<(&'static Register, &'static Mutabil
 
     // Registers are mirrored every 8 bytes
     let mirrored = address % 8;
-    REGISTERS.iter().find_map(|r| {
-        if r.0 == mirrored {
-            Some((&r.1, &r.2))
-        } else {
-            None
-        }
-    })
+    REGISTERS
+        .iter()
+        .find_map(|r| if r.0 == mirrored { Some(&r.1) } else { None })
 }
 
 impl std::fmt::Display for Register {
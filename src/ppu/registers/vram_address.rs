@@ -0,0 +1,117 @@
+use tartan_bitfield::bitfield;
+
+bitfield! {
+    /*
+        yyy NN YYYYY XXXXX
+        ||| || ||||| +++++-- coarse X scroll
+        ||| || +++++-------- coarse Y scroll
+        ||| ++-------------- nametable select
+        +++----------------- fine Y scroll
+    */
+    /// The layout shared by the PPU's current and temporary VRAM address, the "loopy" v and t registers.
+    /// https://www.nesdev.org/wiki/PPU_scrolling#PPU_internal_registers
+    pub struct VramAddress(u16) {
+        [0..=4] pub coarse_x: u8,
+        [5..=9] pub coarse_y: u8,
+        [10..=11] pub nametable: u8,
+        [10] pub nametable_x,
+        [11] pub nametable_y,
+        [12..=14] pub fine_y: u8,
+    }
+}
+
+impl VramAddress {
+    const MASK: u16 = 0x7FFF;
+
+    /// The 14 bits that go out on the address bus
+    pub fn address(&self) -> u16 {
+        u16::from(*self) & 0x3FFF
+    }
+
+    pub fn increment(&mut self, increment: u8) {
+        *self = Self::from(u16::from(*self).wrapping_add(increment as u16) & Self::MASK);
+    }
+
+    /// Move to the next tile, wrapping into the horizontally adjacent nametable.
+    /// https://www.nesdev.org/wiki/PPU_scrolling#Coarse_X_increment
+    pub fn increment_coarse_x(&mut self) {
+        if self.coarse_x() == 31 {
+            self.set_coarse_x(0);
+            self.set_nametable_x(!self.nametable_x());
+        } else {
+            self.set_coarse_x(self.coarse_x() + 1);
+        }
+    }
+
+    /// Move to the next row of pixels, wrapping into the vertically adjacent nametable after the
+    /// last row of tiles. Rows 30 and 31 are attribute data, which wrap without switching nametables.
+    /// https://www.nesdev.org/wiki/PPU_scrolling#Y_increment
+    pub fn increment_y(&mut self) {
+        if self.fine_y() < 7 {
+            self.set_fine_y(self.fine_y() + 1);
+            return;
+        }
+
+        self.set_fine_y(0);
+        match self.coarse_y() {
+            29 => {
+                self.set_coarse_y(0);
+                self.set_nametable_y(!self.nametable_y());
+            }
+            31 => self.set_coarse_y(0),
+            coarse_y => self.set_coarse_y(coarse_y + 1),
+        }
+    }
+
+    /// Copy the horizontal position from another address, done at the end of every scanline
+    pub fn copy_horizontal(&mut self, other: Self) {
+        self.set_coarse_x(other.coarse_x());
+        self.set_nametable_x(other.nametable_x());
+    }
+
+    /// Copy the vertical position from another address, done before rendering a new frame
+    pub fn copy_vertical(&mut self, other: Self) {
+        self.set_coarse_y(other.coarse_y());
+        self.set_nametable_y(other.nametable_y());
+        self.set_fine_y(other.fine_y());
+    }
+
+    /// The nametable byte for the current tile
+    pub fn tile_address(&self) -> u16 {
+        0x2000 | (u16::from(*self) & 0x0FFF)
+    }
+
+    /// The attribute byte covering the current tile, one byte covers 4x4 tiles
+    pub fn attribute_address(&self) -> u16 {
+        0x23C0
+            | ((self.nametable() as u16) << 10)
+            | ((self.coarse_y() as u16 >> 2) << 3)
+            | (self.coarse_x() as u16 >> 2)
+    }
+
+    /// Which of the four 2x2 tile quadrants of the attribute byte the current tile is in
+    pub fn attribute_shift(&self) -> u8 {
+        ((self.coarse_y() & 0b10) << 1) | (self.coarse_x() & 0b10)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn increments_wrap_into_next_nametable() {
+        let mut address = VramAddress::default().with_coarse_x(31).with_coarse_y(29);
+        address.set_fine_y(7);
+
+        address.increment_coarse_x();
+        assert_eq!(address.coarse_x(), 0);
+        assert_eq!(address.nametable(), 0b01);
+
+        address.increment_y();
+        assert_eq!(address.fine_y(), 0);
+        assert_eq!(address.coarse_y(), 0);
+        assert_eq!(address.nametable(), 0b11);
+        assert_eq!(address.tile_address(), 0x2C00);
+    }
+}
@@ -0,0 +1,71 @@
+//! The console region decides how fast the chips are clocked and how long a frame is.
+//! https://www.nesdev.org/wiki/Cycle_reference_chart
+
+use std::{fmt, time::Duration};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Region {
+    #[default]
+    Ntsc,
+    Pal,
+    /// A famiclone that pairs PAL video timing with an NTSC-like CPU to PPU ratio
+    Dendy,
+}
+
+impl Region {
+    /// The frequency of the CPU, in Hz
+    pub const fn cpu_clock_rate(self) -> f64 {
+        match self {
+            Self::Ntsc => 1_789_773.0,
+            Self::Pal => 1_662_607.0,
+            Self::Dendy => 1_773_448.0,
+        }
+    }
+
+    /// How many PPU dots pass during the given number of CPU cycles, as a fraction
+    pub const fn dots_per_cycles(self) -> (usize, usize) {
+        match self {
+            Self::Ntsc | Self::Dendy => (3, 1),
+            Self::Pal => (16, 5),
+        }
+    }
+
+    /// Scanlines per frame, including the pre-render scanline
+    pub const fn scanlines(self) -> u16 {
+        match self {
+            Self::Ntsc => 262,
+            Self::Pal | Self::Dendy => 312,
+        }
+    }
+
+    /// The scanline at which vblank starts. The Dendy waits 50 scanlines so its vblank is as short as NTSC.
+    pub const fn vblank_scanline(self) -> u16 {
+        match self {
+            Self::Ntsc | Self::Pal => 241,
+            Self::Dendy => 291,
+        }
+    }
+
+    /// NTSC runs at ~60.0988 FPS, the others at ~50.0070 FPS
+    pub const fn frame_duration(self) -> Duration {
+        match self {
+            Self::Ntsc => Duration::from_nanos(16_639_267),
+            Self::Pal | Self::Dendy => Duration::from_nanos(19_997_209),
+        }
+    }
+
+    /// Whether the APU uses the PAL period tables and frame counter timing. The Dendy clones the NTSC APU.
+    pub const fn pal_apu(self) -> bool {
+        matches!(self, Self::Pal)
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ntsc => write!(f, "NTSC"),
+            Self::Pal => write!(f, "PAL"),
+            Self::Dendy => write!(f, "Dendy"),
+        }
+    }
+}
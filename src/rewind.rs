@@ -0,0 +1,131 @@
+//! Rewinding through the last few seconds of emulation.
+//!
+//! A save state is taken every frame, but only the most recent one is kept whole. Older ones are stored as the
+//! XOR with the state after them, which is mostly zeroes and run-length encodes well.
+
+use crate::{cpu::Cpu, savestate, util::CircularBuffer};
+
+/// About ten seconds at 60 frames per second
+const HISTORY_LEN: usize = 600;
+
+pub struct Rewind {
+    /// The state of the most recent frame
+    current: Option<Vec<u8>>,
+    /// Each delta turns a state back into the one that came before it
+    deltas: CircularBuffer<Vec<u8>, HISTORY_LEN>,
+}
+
+impl Rewind {
+    pub const fn new() -> Self {
+        Self {
+            current: None,
+            deltas: CircularBuffer::new(),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.current = None;
+        self.deltas.clear();
+    }
+
+    /// Remember the state of the machine, called once per frame
+    pub fn push(&mut self, cpu: &Cpu) {
+        let state = savestate::save(cpu);
+        if let Some(previous) = self.current.replace(state) {
+            let delta = encode(&previous, self.current.as_ref().unwrap());
+            self.deltas.push(delta);
+        }
+    }
+
+    /// Restore the state from one frame earlier, returns false when the history ran out
+    pub fn pop(&mut self, cpu: &mut Cpu) -> bool {
+        let (Some(current), Some(delta)) = (&self.current, self.deltas.pop()) else {
+            return false;
+        };
+
+        let previous = decode(current, &delta);
+        if let Err(err) = savestate::load(cpu, &previous) {
+            tracing::error!("failed to rewind: {err}");
+            self.clear();
+            return false;
+        }
+
+        self.current = Some(previous);
+        true
+    }
+}
+
+/// The delta starts with the length of the older state, as an `Option` in the state can make it differ.
+/// The XOR follows as alternating runs of zeroes and literal bytes, each prefixed with their 16-bit length.
+fn encode(older: &[u8], newer: &[u8]) -> Vec<u8> {
+    let len = older.len().max(newer.len());
+    let xor = (0..len).map(|i| older.get(i).unwrap_or(&0) ^ newer.get(i).unwrap_or(&0));
+
+    let mut delta = (older.len() as u32).to_le_bytes().to_vec();
+    let mut zeroes: u16 = 0;
+    let mut literals = Vec::new();
+
+    for byte in xor {
+        if byte == 0 && literals.is_empty() && zeroes < u16::MAX {
+            zeroes += 1;
+        } else if byte != 0 && literals.len() < u16::MAX as usize {
+            literals.push(byte);
+        } else {
+            delta.extend_from_slice(&zeroes.to_le_bytes());
+            delta.extend_from_slice(&(literals.len() as u16).to_le_bytes());
+            delta.append(&mut literals);
+            zeroes = (byte == 0) as u16;
+            if byte != 0 {
+                literals.push(byte);
+            }
+        }
+    }
+
+    if zeroes > 0 || !literals.is_empty() {
+        delta.extend_from_slice(&zeroes.to_le_bytes());
+        delta.extend_from_slice(&(literals.len() as u16).to_le_bytes());
+        delta.append(&mut literals);
+    }
+    delta
+}
+
+fn decode(newer: &[u8], delta: &[u8]) -> Vec<u8> {
+    let older_len = u32::from_le_bytes(delta[..4].try_into().unwrap()) as usize;
+    let mut xor = Vec::with_capacity(older_len.max(newer.len()));
+
+    let mut runs = &delta[4..];
+    while let [zeroes_low, zeroes_high, literals_low, literals_high, rest @ ..] = runs {
+        let zeroes = u16::from_le_bytes([*zeroes_low, *zeroes_high]) as usize;
+        let literals = u16::from_le_bytes([*literals_low, *literals_high]) as usize;
+        xor.resize(xor.len() + zeroes, 0);
+        xor.extend_from_slice(&rest[..literals]);
+        runs = &rest[literals..];
+    }
+
+    xor.iter()
+        .enumerate()
+        .map(|(i, byte)| byte ^ newer.get(i).unwrap_or(&0))
+        .take(older_len)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delta_roundtrip() {
+        let older: Vec<u8> = (0..200_000).map(|i| (i / 7) as u8).collect();
+        let mut newer = older.clone();
+        newer[3] = 0xFF;
+        newer[100_000..100_010].fill(0);
+        newer.push(1);
+
+        let delta = encode(&older, &newer);
+        assert!(delta.len() < 100);
+        assert_eq!(decode(&newer, &delta), older);
+
+        let shorter = &older[..older.len() - 5];
+        assert_eq!(decode(&newer, &encode(shorter, &newer)), shorter);
+    }
+}
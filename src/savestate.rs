@@ -0,0 +1,319 @@
+//! A versioned binary format to snapshot and restore the entire machine.
+//!
+//! Every component implements `Snapshot`, writing its fields in a fixed order. There are no field names or
+//! lengths in the format itself, so any change to what gets saved must bump `VERSION`.
+
+use crate::cpu::Cpu;
+use std::path::{Path, PathBuf};
+
+const MAGIC: [u8; 4] = *b"NESS";
+const VERSION: u16 = 7;
+
+pub const SLOTS: u8 = 10;
+
+pub enum SaveStateRequest {
+    Save(u8),
+    Load(u8),
+}
+
+pub trait Snapshot {
+    fn save(&self, state: &mut StateWriter);
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String>;
+}
+
+#[derive(Default)]
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub const fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .data
+            .get(self.position..self.position + len)
+            .ok_or("save state is truncated")?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    pub fn read<T: Snapshot + Default>(&mut self) -> Result<T, String> {
+        let mut value = T::default();
+        value.load(self)?;
+        Ok(value)
+    }
+
+    const fn is_empty(&self) -> bool {
+        self.position >= self.data.len()
+    }
+}
+
+macro_rules! snapshot_number {
+    ($($type:ty),*) => {
+        $(
+            impl Snapshot for $type {
+                fn save(&self, state: &mut StateWriter) {
+                    state.write_bytes(&self.to_le_bytes());
+                }
+
+                fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+                    let bytes = state.read_bytes(std::mem::size_of::<$type>())?;
+                    *self = <$type>::from_le_bytes(bytes.try_into().unwrap());
+                    Ok(())
+                }
+            }
+        )*
+    };
+}
+
+snapshot_number!(u8, u16, u32, u64, i16, f32);
+
+impl Snapshot for usize {
+    fn save(&self, state: &mut StateWriter) {
+        // Stored as 64 bits so states can be shared between platforms
+        (*self as u64).save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        *self = state.read::<u64>()? as usize;
+        Ok(())
+    }
+}
+
+impl Snapshot for bool {
+    fn save(&self, state: &mut StateWriter) {
+        (*self as u8).save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        *self = state.read::<u8>()? != 0;
+        Ok(())
+    }
+}
+
+impl<T: Snapshot, const N: usize> Snapshot for [T; N] {
+    fn save(&self, state: &mut StateWriter) {
+        self.iter().for_each(|value| value.save(state));
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.iter_mut().try_for_each(|value| value.load(state))
+    }
+}
+
+impl Snapshot for Vec<u8> {
+    fn save(&self, state: &mut StateWriter) {
+        self.len().save(state);
+        state.write_bytes(self);
+    }
+
+    /// The length is fixed by the cartridge, so it has to match
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        let len = state.read::<usize>()?;
+        if len != self.len() {
+            return Err(format!(
+                "save state has {len} bytes of memory where {} were expected",
+                self.len()
+            ));
+        }
+        self.copy_from_slice(state.read_bytes(len)?);
+        Ok(())
+    }
+}
+
+impl<T: Snapshot + Default> Snapshot for Option<T> {
+    fn save(&self, state: &mut StateWriter) {
+        self.is_some().save(state);
+        if let Some(value) = self {
+            value.save(state);
+        }
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        *self = if state.read::<bool>()? {
+            Some(state.read()?)
+        } else {
+            None
+        };
+        Ok(())
+    }
+}
+
+/// Implement `Snapshot` for a struct by saving the given fields in order
+macro_rules! snapshot_fields {
+    ($type:ty { $($($field:ident).+),* $(,)? }) => {
+        impl $crate::savestate::Snapshot for $type {
+            fn save(&self, state: &mut $crate::savestate::StateWriter) {
+                $( $crate::savestate::Snapshot::save(&self.$($field).+, state); )*
+            }
+
+            fn load(&mut self, state: &mut $crate::savestate::StateReader) -> Result<(), String> {
+                $( $crate::savestate::Snapshot::load(&mut self.$($field).+, state)?; )*
+                Ok(())
+            }
+        }
+    };
+}
+
+/// Implement `Snapshot` for bitfields, by saving their underlying value
+macro_rules! snapshot_bitfield {
+    ($($type:ty: $underlying:ty),* $(,)?) => {
+        $(
+            impl $crate::savestate::Snapshot for $type {
+                fn save(&self, state: &mut $crate::savestate::StateWriter) {
+                    $crate::savestate::Snapshot::save(&<$underlying>::from(*self), state);
+                }
+
+                fn load(&mut self, state: &mut $crate::savestate::StateReader) -> Result<(), String> {
+                    *self = state.read::<$underlying>()?.into();
+                    Ok(())
+                }
+            }
+        )*
+    };
+}
+
+pub(crate) use {snapshot_bitfield, snapshot_fields};
+
+/// FNV-1a, used to make sure a save state belongs to the loaded ROM.
+/// The standard library's hasher is not guaranteed to be stable between releases.
+pub fn checksum<'a>(data: impl IntoIterator<Item = &'a u8>) -> u64 {
+    data.into_iter().fold(0xCBF2_9CE4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01B3)
+    })
+}
+
+/// Save states are stored next to the ROM, e.g. `game.ss1` for slot one of `game.nes`
+pub fn slot_path(rom_path: &Path, slot: u8) -> PathBuf {
+    rom_path.with_extension(format!("ss{slot}"))
+}
+
+pub fn save(cpu: &Cpu) -> Vec<u8> {
+    let mut state = StateWriter::default();
+    state.write_bytes(&MAGIC);
+    VERSION.save(&mut state);
+    cpu.bus.rom_checksum.save(&mut state);
+    cpu.save(&mut state);
+    state.into_inner()
+}
+
+pub fn load(cpu: &mut Cpu, data: &[u8]) -> Result<(), String> {
+    let mut state = StateReader::new(data);
+    if state.read_bytes(MAGIC.len())? != MAGIC {
+        return Err("not a save state".to_string());
+    }
+
+    let version = state.read::<u16>()?;
+    if version != VERSION {
+        return Err(format!(
+            "unsupported save state version {version}, expected {VERSION}"
+        ));
+    }
+
+    if state.read::<u64>()? != cpu.bus.rom_checksum {
+        return Err("save state belongs to a different ROM".to_string());
+    }
+
+    // Loading can fail halfway through, in which case the machine would be left in a mix of both states
+    let backup = save(cpu);
+    let result = cpu.load(&mut state).and_then(|_| {
+        if state.is_empty() {
+            Ok(())
+        } else {
+            Err("save state has trailing data".to_string())
+        }
+    });
+
+    if result.is_err() {
+        let mut backup = StateReader::new(&backup[MAGIC.len() + 2 + 8..]);
+        cpu.load(&mut backup).expect("failed to restore state");
+    }
+    result
+}
+
+/// Save or load the given slot for the ROM that is currently running
+pub fn handle_request(cpu: &mut Cpu, request: SaveStateRequest) {
+    let _span = tracing::span!(tracing::Level::INFO, "savestate").entered();
+    let Some(rom_path) = cpu.bus.rom_path.clone() else {
+        tracing::warn!("no ROM loaded, ignoring save state request");
+        return;
+    };
+
+    match request {
+        SaveStateRequest::Save(slot) => {
+            let path = slot_path(&rom_path, slot);
+            match std::fs::write(&path, save(cpu)) {
+                Ok(()) => tracing::info!("saved state to {}", path.display()),
+                Err(err) => tracing::error!("failed to save state to {}: {err}", path.display()),
+            }
+        }
+
+        SaveStateRequest::Load(slot) => {
+            let path = slot_path(&rom_path, slot);
+            let result = std::fs::read(&path)
+                .map_err(|err| err.to_string())
+                .and_then(|data| load(cpu, &data));
+
+            match result {
+                Ok(()) => tracing::info!("loaded state from {}", path.display()),
+                Err(err) => tracing::error!("failed to load state from {}: {err}", path.display()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Example {
+        a: u8,
+        b: u16,
+        c: Option<u8>,
+        d: [bool; 2],
+    }
+
+    snapshot_fields!(Example { a, b, c, d });
+
+    #[test]
+    fn roundtrip() {
+        let example = Example {
+            a: 1,
+            b: 0x1234,
+            c: Some(3),
+            d: [true, false],
+        };
+
+        let mut state = StateWriter::default();
+        example.save(&mut state);
+        let data = state.into_inner();
+        assert_eq!(data, [1, 0x34, 0x12, 1, 3, 1, 0]);
+
+        let loaded = StateReader::new(&data).read::<Example>().unwrap();
+        assert_eq!(loaded.a, 1);
+        assert_eq!(loaded.b, 0x1234);
+        assert_eq!(loaded.c, Some(3));
+        assert_eq!(loaded.d, [true, false]);
+
+        assert!(StateReader::new(&data[..3]).read::<Example>().is_err());
+    }
+}
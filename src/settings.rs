@@ -0,0 +1,60 @@
+//! Settings that are remembered between sessions, stored as `key=value` lines in the config directory.
+
+use std::{collections::BTreeMap, fs, path::PathBuf};
+
+/// The [`PaletteChoice`](crate::ppu::palette::PaletteChoice) to render with
+pub const PALETTE_SETTING: &str = "palette";
+/// Whether the NTSC filter is enabled
+pub const NTSC_FILTER_SETTING: &str = "ntsc_filter";
+
+#[derive(Default)]
+pub struct Settings {
+    values: BTreeMap<String, String>,
+}
+
+impl Settings {
+    fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("nes-emu").join("settings"))
+    }
+
+    /// Read the settings from disk, a missing or unreadable file gives the defaults
+    pub fn load() -> Self {
+        let Some(contents) = Self::path().and_then(|path| fs::read_to_string(path).ok()) else {
+            return Self::default();
+        };
+
+        let values = contents
+            .lines()
+            .filter_map(|line| line.split_once('='))
+            .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+            .collect();
+        Self { values }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(String::as_str)
+    }
+
+    /// Change a setting and write all of them to disk
+    pub fn set(&mut self, key: &str, value: impl ToString) {
+        self.values.insert(key.to_string(), value.to_string());
+
+        let Some(path) = Self::path() else {
+            tracing::warn!("no config directory, not saving setting '{key}'");
+            return;
+        };
+
+        let contents: String = self
+            .values
+            .iter()
+            .map(|(key, value)| format!("{key}={value}\n"))
+            .collect();
+        if let Err(err) = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(&path, contents))
+        {
+            tracing::error!("failed to save settings to {}: {err}", path.display());
+        }
+    }
+}
@@ -57,6 +57,14 @@ impl<T, const N: usize> CircularBuffer<T, N> {
         self.data[self.current.saturating_sub(1)].as_ref()
     }
 
+    /// Remove the last value pushed into the buffer, making the one before it the last.
+    pub fn pop(&mut self) -> Option<T> {
+        let previous = (self.current + N - 1) % N;
+        let value = self.data[previous].take()?;
+        self.current = previous;
+        Some(value)
+    }
+
     pub fn clear(&mut self) {
         self.data = [Self::DEFAULT; N];
         self.current = 0;
@@ -99,4 +107,19 @@ mod test {
         assert!(!nth_bit(value, 6));
         assert!(nth_bit(value, 7));
     }
+
+    #[test]
+    fn test_circular_buffer_pop() {
+        let mut buffer = CircularBuffer::<u8, 3>::new();
+        assert_eq!(buffer.pop(), None);
+
+        (1..=4).for_each(|value| buffer.push(value));
+        assert_eq!(buffer.pop(), Some(4));
+        assert_eq!(buffer.pop(), Some(3));
+        buffer.push(5);
+        assert_eq!(buffer.pop(), Some(5));
+        assert_eq!(buffer.pop(), Some(2));
+        // The oldest value was overwritten
+        assert_eq!(buffer.pop(), None);
+    }
 }